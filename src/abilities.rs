@@ -1,17 +1,31 @@
 // In a new abilities.rs file
+use std::collections::BTreeSet;
+
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Reflect, Serialize, Deserialize)]
+pub enum Ability {
+    /// Blocking comes from a shield in the off hand now, this is only still read from old saves
+    Block,
+}
 
-#[derive(Component, Default)]
+/// What old saves had unlocked. Nothing unlocks abilities any more, saves only read this to migrate.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct Abilities {
-    can_block: bool,
+    unlocked: BTreeSet<Ability>,
 }
 
 impl Abilities {
-    pub fn unlock_block(&mut self) {
-        self.can_block = true;
+    pub fn is_empty(&self) -> bool {
+        self.unlocked.is_empty()
     }
 
-    pub fn can_block(&self) -> bool {
-        self.can_block
+    pub fn has(&self, ability: Ability) -> bool {
+        self.unlocked.contains(&ability)
     }
-}
\ No newline at end of file
+
+    pub fn remove(&mut self, ability: Ability) {
+        self.unlocked.remove(&ability);
+    }
+}
@@ -0,0 +1,622 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use bevy::{input::InputSystem, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::locale::Localizer;
+
+/// Where rebound controls are persisted between runs
+const BINDINGS_PATH: &str = "config/bindings.ron";
+
+/// How far a stick has to be pushed before it counts as a button press
+const AXIS_THRESHOLD: f32 = 0.5;
+
+/// Everything the game reacts to. Gameplay systems read these through
+/// `Res<ButtonInput<Action>>` and never look at keys or gamepad buttons directly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, Reflect)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    Jump,
+    Attack,
+    Block,
+    Interact,
+    Restart,
+    ToggleDebug,
+    MenuUp,
+    MenuDown,
+    MenuConfirm,
+    MenuBack,
+    OpenBindings,
+    SwitchLanguage,
+    ToggleQuestLog,
+    SaveGame,
+    LoadGame,
+    ToggleMap,
+    ToggleInventory,
+    Pause,
+}
+
+impl Action {
+    /// Actions shown in the rebinding menu, in display order
+    pub const ALL: [Action; 20] = [
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::Jump,
+        Action::Attack,
+        Action::Block,
+        Action::Interact,
+        Action::Restart,
+        Action::ToggleDebug,
+        Action::MenuUp,
+        Action::MenuDown,
+        Action::MenuConfirm,
+        Action::MenuBack,
+        Action::OpenBindings,
+        Action::SwitchLanguage,
+        Action::ToggleQuestLog,
+        Action::SaveGame,
+        Action::LoadGame,
+        Action::ToggleMap,
+        Action::ToggleInventory,
+        Action::Pause,
+    ];
+
+    /// Menu actions keep working while gameplay input is locked
+    pub fn is_menu(&self) -> bool {
+        matches!(
+            self,
+            Action::MenuUp
+                | Action::MenuDown
+                | Action::MenuConfirm
+                | Action::MenuBack
+                | Action::OpenBindings
+                | Action::SwitchLanguage
+                | Action::ToggleMap
+                | Action::ToggleInventory
+                | Action::Pause
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AxisDirection {
+    Positive,
+    Negative,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InputBinding {
+    Key(KeyCode),
+    GamepadButton(GamepadButton),
+    GamepadAxis(GamepadAxis, AxisDirection),
+}
+
+impl InputBinding {
+    fn is_active(&self, keys: &ButtonInput<KeyCode>, gamepads: &Query<&Gamepad>) -> bool {
+        match self {
+            InputBinding::Key(key) => keys.pressed(*key),
+            InputBinding::GamepadButton(button) => gamepads.iter().any(|gamepad| gamepad.pressed(*button)),
+            InputBinding::GamepadAxis(axis, direction) => gamepads.iter().any(|gamepad| {
+                let value = gamepad.get(*axis).unwrap_or(0.0);
+                match direction {
+                    AxisDirection::Positive => value > AXIS_THRESHOLD,
+                    AxisDirection::Negative => value < -AXIS_THRESHOLD,
+                }
+            }),
+        }
+    }
+
+    fn is_keyboard(&self) -> bool {
+        matches!(self, InputBinding::Key(_))
+    }
+
+    /// How the binding is shown in prompts and the bindings menu. Letters, digits and function keys
+    /// come back as they are, anything with a name as an `input.*` key into the string tables.
+    /// [Localizer::format] translates those like any other argument.
+    pub fn label(&self) -> String {
+        match self {
+            InputBinding::Key(key) => key_label(*key),
+            InputBinding::GamepadButton(button) => format!("input.button.{:?}", button),
+            InputBinding::GamepadAxis(axis, direction) => {
+                let positive = *direction == AxisDirection::Positive;
+                let (stick, directions) = match axis {
+                    GamepadAxis::LeftStickX => ("left_stick", ["left", "right"]),
+                    GamepadAxis::LeftStickY => ("left_stick", ["down", "up"]),
+                    GamepadAxis::RightStickX => ("right_stick", ["left", "right"]),
+                    GamepadAxis::RightStickY => ("right_stick", ["down", "up"]),
+                    _ => return format!("{:?}{}", axis, if positive { "+" } else { "-" }),
+                };
+                format!("input.{}.{}", stick, directions[positive as usize])
+            }
+        }
+    }
+
+    /// [InputBinding::label], translated
+    pub fn localized_label(&self, localizer: &Localizer) -> String {
+        let label = self.label();
+        localizer.get_or(&label, &label)
+    }
+}
+
+/// Keys with a name in the string tables, as `input.key.<KeyCode>`
+const NAMED_KEYS: [KeyCode; 22] = [
+    KeyCode::Space,
+    KeyCode::Enter,
+    KeyCode::Escape,
+    KeyCode::Backspace,
+    KeyCode::Tab,
+    KeyCode::Delete,
+    KeyCode::Insert,
+    KeyCode::Home,
+    KeyCode::End,
+    KeyCode::PageUp,
+    KeyCode::PageDown,
+    KeyCode::ArrowUp,
+    KeyCode::ArrowDown,
+    KeyCode::ArrowLeft,
+    KeyCode::ArrowRight,
+    KeyCode::ShiftLeft,
+    KeyCode::ShiftRight,
+    KeyCode::ControlLeft,
+    KeyCode::ControlRight,
+    KeyCode::AltLeft,
+    KeyCode::AltRight,
+    KeyCode::CapsLock,
+];
+
+/// Drops the `Key`/`Digit` prefixes from everything without a translated name
+fn key_label(key: KeyCode) -> String {
+    let name = format!("{:?}", key);
+    if NAMED_KEYS.contains(&key) {
+        return format!("input.key.{}", name);
+    }
+    for prefix in ["Key", "Digit"] {
+        if let Some(rest) = name.strip_prefix(prefix) {
+            return rest.to_string();
+        }
+    }
+    if let Some(rest) = name.strip_prefix("Numpad") {
+        return format!("Num {}", rest);
+    }
+    name
+}
+
+/// Keyboard and gamepad bindings for every [Action]
+#[derive(Debug, Clone, Resource, Serialize, Deserialize)]
+pub struct ActionBindings(pub BTreeMap<Action, Vec<InputBinding>>);
+
+impl Default for ActionBindings {
+    fn default() -> Self {
+        let key = InputBinding::Key;
+        let button = InputBinding::GamepadButton;
+        let stick_x = |direction| InputBinding::GamepadAxis(GamepadAxis::LeftStickX, direction);
+
+        let mut bindings = BTreeMap::new();
+        bindings.insert(Action::MoveLeft, vec![
+            key(KeyCode::ArrowLeft),
+            button(GamepadButton::DPadLeft),
+            stick_x(AxisDirection::Negative),
+        ]);
+        bindings.insert(Action::MoveRight, vec![
+            key(KeyCode::ArrowRight),
+            button(GamepadButton::DPadRight),
+            stick_x(AxisDirection::Positive),
+        ]);
+        bindings.insert(Action::Jump, vec![
+            key(KeyCode::Space),
+            key(KeyCode::ArrowUp),
+            button(GamepadButton::South),
+        ]);
+        bindings.insert(Action::Attack, vec![key(KeyCode::KeyA), button(GamepadButton::West)]);
+        bindings.insert(Action::Block, vec![key(KeyCode::KeyD), button(GamepadButton::RightTrigger)]);
+        bindings.insert(Action::Interact, vec![key(KeyCode::KeyE), button(GamepadButton::North)]);
+        bindings.insert(Action::Restart, vec![key(KeyCode::KeyR)]);
+        bindings.insert(Action::ToggleDebug, vec![key(KeyCode::KeyV)]);
+        bindings.insert(Action::MenuUp, vec![key(KeyCode::ArrowUp), button(GamepadButton::DPadUp)]);
+        bindings.insert(Action::MenuDown, vec![key(KeyCode::ArrowDown), button(GamepadButton::DPadDown)]);
+        bindings.insert(Action::MenuConfirm, vec![key(KeyCode::Enter), button(GamepadButton::South)]);
+        bindings.insert(Action::MenuBack, vec![key(KeyCode::Escape), button(GamepadButton::East)]);
+        bindings.insert(Action::OpenBindings, vec![key(KeyCode::F1), button(GamepadButton::Select)]);
+        bindings.insert(Action::SwitchLanguage, vec![key(KeyCode::F2)]);
+        bindings.insert(Action::ToggleQuestLog, vec![key(KeyCode::KeyQ), button(GamepadButton::LeftTrigger2)]);
+        bindings.insert(Action::SaveGame, vec![key(KeyCode::F5)]);
+        bindings.insert(Action::LoadGame, vec![key(KeyCode::F9)]);
+        bindings.insert(Action::ToggleMap, vec![key(KeyCode::KeyM), button(GamepadButton::LeftTrigger)]);
+        bindings.insert(Action::ToggleInventory, vec![key(KeyCode::KeyI), button(GamepadButton::RightThumb)]);
+        bindings.insert(Action::Pause, vec![key(KeyCode::KeyP), button(GamepadButton::Start)]);
+
+        ActionBindings(bindings)
+    }
+}
+
+impl ActionBindings {
+    pub fn get(&self, action: Action) -> &[InputBinding] {
+        self.0.get(&action).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Replaces the primary binding of the same device kind (keyboard or gamepad) for `action`,
+    /// keeping its alternates and the other device's bindings untouched.
+    pub fn rebind(&mut self, action: Action, binding: InputBinding) {
+        let bindings = self.0.entry(action).or_default();
+        // Binding to what was an alternate moves it up rather than listing it twice
+        bindings.retain(|existing| *existing != binding);
+        match bindings.iter().position(|existing| existing.is_keyboard() == binding.is_keyboard()) {
+            Some(primary) => bindings[primary] = binding,
+            None => bindings.insert(0, binding),
+        }
+    }
+
+    pub fn load() -> Self {
+        let Ok(contents) = fs::read_to_string(BINDINGS_PATH) else {
+            return ActionBindings::default();
+        };
+
+        match ron::from_str::<ActionBindings>(&contents) {
+            Ok(mut bindings) => {
+                // Actions added after the file was written fall back to their defaults
+                for (action, default) in ActionBindings::default().0 {
+                    bindings.0.entry(action).or_insert(default);
+                }
+                bindings
+            }
+            Err(err) => {
+                warn!("Failed to parse {}, using default bindings: {}", BINDINGS_PATH, err);
+                ActionBindings::default()
+            }
+        }
+    }
+
+    pub fn save(&self) {
+        let result = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|err| err.to_string())
+            .and_then(|contents| {
+                if let Some(dir) = Path::new(BINDINGS_PATH).parent() {
+                    fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+                }
+                fs::write(BINDINGS_PATH, contents).map_err(|err| err.to_string())
+            });
+
+        if let Err(err) = result {
+            warn!("Failed to save bindings to {}: {}", BINDINGS_PATH, err);
+        }
+    }
+}
+
+/// The menus that take the controls away from the player
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Menu {
+    Bindings,
+    Shop,
+    SaveSlots,
+    Map,
+    Inventory,
+    /// The pause and game over screens
+    Pause,
+}
+
+/// Blocks gameplay actions while something else owns the controls.
+/// An open menu or a cutscene leaves only menu actions, dialogue also
+/// keeps Interact so it can be used to advance the text.
+#[derive(Debug, Default, Resource)]
+pub struct InputLock {
+    /// Only one menu is open at a time, the others stay shut until it closes
+    pub menu: Option<Menu>,
+    pub cutscene: bool,
+    pub dialogue: bool,
+}
+
+impl InputLock {
+    /// Hands the controls to `menu`, unless a different menu already has them
+    pub fn open_menu(&mut self, menu: Menu) -> bool {
+        if self.menu.is_some_and(|owner| owner != menu) {
+            return false;
+        }
+        self.menu = Some(menu);
+        true
+    }
+
+    /// Takes the controls back from `menu`, leaving them with any other menu that has them
+    pub fn close_menu(&mut self, menu: Menu) {
+        if self.menu == Some(menu) {
+            self.menu = None;
+        }
+    }
+
+    pub fn allows(&self, action: Action) -> bool {
+        if action.is_menu() {
+            true
+        } else if self.menu.is_some() || self.cutscene {
+            false
+        } else if self.dialogue {
+            action == Action::Interact
+        } else {
+            true
+        }
+    }
+}
+
+pub fn update_action_state(
+    bindings: Res<ActionBindings>,
+    lock: Res<InputLock>,
+    keys: Res<ButtonInput<KeyCode>>,
+    gamepads: Query<&Gamepad>,
+    mut actions: ResMut<ButtonInput<Action>>,
+) {
+    actions.clear();
+
+    for action in Action::ALL {
+        let active = lock.allows(action)
+            && bindings
+                .get(action)
+                .iter()
+                .any(|binding| binding.is_active(&keys, &gamepads));
+
+        if active {
+            actions.press(action);
+        } else {
+            actions.release(action);
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct BindingsMenu;
+
+#[derive(Component)]
+pub struct BindingRow(pub usize);
+
+#[derive(Debug, Default, Resource)]
+pub struct BindingsMenuState {
+    pub open: bool,
+    pub selected: usize,
+    /// Set while the menu waits for the next key or button to bind
+    pub awaiting_input: bool,
+}
+
+pub fn spawn_bindings_menu(mut commands: Commands) {
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(40.0),
+                left: Val::Px(40.0),
+                padding: UiRect::all(Val::Px(10.0)),
+                flex_direction: FlexDirection::Column,
+                ..Default::default()
+            },
+            Visibility::Hidden,
+            BackgroundColor::from(Color::srgba(0.1, 0.1, 0.1, 0.9)),
+            BindingsMenu,
+        ))
+        .with_children(|parent| {
+            for (index, _) in Action::ALL.iter().enumerate() {
+                parent.spawn((
+                    Text::new(""),
+                    TextFont {
+                        font_size: 16.0,
+                        ..Default::default()
+                    },
+                    TextColor(Color::WHITE),
+                    BindingRow(index),
+                ));
+            }
+        });
+}
+
+pub fn toggle_bindings_menu(
+    actions: Res<ButtonInput<Action>>,
+    mut menu_state: ResMut<BindingsMenuState>,
+    mut lock: ResMut<InputLock>,
+    bindings: Res<ActionBindings>,
+) {
+    if menu_state.awaiting_input {
+        return;
+    }
+
+    let toggle = actions.just_pressed(Action::OpenBindings);
+    if menu_state.open && (toggle || actions.just_pressed(Action::MenuBack)) {
+        menu_state.open = false;
+        lock.close_menu(Menu::Bindings);
+        bindings.save();
+    } else if toggle {
+        // Stays shut while another menu has the controls
+        menu_state.open = lock.open_menu(Menu::Bindings);
+    }
+}
+
+pub fn navigate_bindings_menu(
+    actions: Res<ButtonInput<Action>>,
+    keys: Res<ButtonInput<KeyCode>>,
+    gamepads: Query<&Gamepad>,
+    mut menu_state: ResMut<BindingsMenuState>,
+    mut bindings: ResMut<ActionBindings>,
+) {
+    if !menu_state.open {
+        return;
+    }
+
+    if menu_state.awaiting_input {
+        let key = keys.get_just_pressed().next().copied().map(InputBinding::Key);
+        let button = gamepads
+            .iter()
+            .find_map(|gamepad| gamepad.get_just_pressed().next().copied())
+            .map(InputBinding::GamepadButton);
+
+        if let Some(binding) = key.or(button) {
+            bindings.rebind(Action::ALL[menu_state.selected], binding);
+            menu_state.awaiting_input = false;
+        }
+        return;
+    }
+
+    if actions.just_pressed(Action::MenuUp) {
+        menu_state.selected = (menu_state.selected + Action::ALL.len() - 1) % Action::ALL.len();
+    }
+    if actions.just_pressed(Action::MenuDown) {
+        menu_state.selected = (menu_state.selected + 1) % Action::ALL.len();
+    }
+    if actions.just_pressed(Action::MenuConfirm) {
+        menu_state.awaiting_input = true;
+    }
+}
+
+pub fn update_bindings_menu(
+    menu_state: Res<BindingsMenuState>,
+    bindings: Res<ActionBindings>,
+    localizer: Localizer,
+    mut menu_query: Query<&mut Visibility, With<BindingsMenu>>,
+    mut row_query: Query<(&mut Text, &mut TextColor, &BindingRow)>,
+) {
+    if !menu_state.is_changed() && !bindings.is_changed() && !localizer.changed() {
+        return;
+    }
+
+    for mut visibility in menu_query.iter_mut() {
+        *visibility = if menu_state.open {
+            Visibility::Visible
+        } else {
+            Visibility::Hidden
+        };
+    }
+
+    for (mut text, mut color, row) in row_query.iter_mut() {
+        let action = Action::ALL[row.0];
+        let selected = row.0 == menu_state.selected;
+
+        let bound = if selected && menu_state.awaiting_input {
+            localizer.get("menu.bindings.waiting")
+        } else {
+            bindings
+                .get(action)
+                .iter()
+                .map(|binding| binding.localized_label(&localizer))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        text.0 = format!("{}: {}", localizer.get(&format!("action.{:?}", action)), bound);
+        color.0 = if selected { Color::srgb(1.0, 0.85, 0.3) } else { Color::WHITE };
+    }
+}
+
+pub struct ActionsPlugin;
+
+impl Plugin for ActionsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .register_type::<Action>()
+            .insert_resource(ActionBindings::load())
+            .init_resource::<ButtonInput<Action>>()
+            .init_resource::<InputLock>()
+            .init_resource::<BindingsMenuState>()
+            .add_systems(Startup, spawn_bindings_menu)
+            .add_systems(PreUpdate, update_action_state.after(InputSystem))
+            .add_systems(Update, (
+                toggle_bindings_menu,
+                navigate_bindings_menu,
+                update_bindings_menu,
+            ).chain());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_menu_owns_the_lock() {
+        let mut lock = InputLock::default();
+        assert!(lock.open_menu(Menu::Map));
+        assert!(!lock.open_menu(Menu::Inventory));
+        assert!(!lock.allows(Action::Jump));
+
+        // Closing a menu that never opened leaves the open one locked
+        lock.close_menu(Menu::Inventory);
+        assert_eq!(lock.menu, Some(Menu::Map));
+
+        lock.close_menu(Menu::Map);
+        assert!(lock.allows(Action::Jump));
+        assert!(lock.open_menu(Menu::Inventory));
+    }
+
+    #[test]
+    fn labels_are_readable() {
+        assert_eq!(InputBinding::Key(KeyCode::KeyE).label(), "E");
+        assert_eq!(InputBinding::Key(KeyCode::Digit1).label(), "1");
+        assert_eq!(InputBinding::Key(KeyCode::F5).label(), "F5");
+        assert_eq!(InputBinding::Key(KeyCode::ShiftLeft).label(), "input.key.ShiftLeft");
+        assert_eq!(InputBinding::GamepadButton(GamepadButton::South).label(), "input.button.South");
+        assert_eq!(
+            InputBinding::GamepadAxis(GamepadAxis::LeftStickX, AxisDirection::Negative).label(),
+            "input.left_stick.left"
+        );
+    }
+
+    #[test]
+    fn input_names_are_translated() {
+        let assets = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/locale");
+        let buttons = [
+            GamepadButton::South,
+            GamepadButton::East,
+            GamepadButton::West,
+            GamepadButton::North,
+            GamepadButton::LeftTrigger,
+            GamepadButton::LeftTrigger2,
+            GamepadButton::RightTrigger,
+            GamepadButton::RightTrigger2,
+            GamepadButton::LeftThumb,
+            GamepadButton::RightThumb,
+            GamepadButton::DPadUp,
+            GamepadButton::DPadDown,
+            GamepadButton::DPadLeft,
+            GamepadButton::DPadRight,
+            GamepadButton::Select,
+            GamepadButton::Start,
+            GamepadButton::Mode,
+        ];
+        let axes = [GamepadAxis::LeftStickX, GamepadAxis::LeftStickY, GamepadAxis::RightStickX, GamepadAxis::RightStickY];
+        let labels: Vec<String> = NAMED_KEYS
+            .into_iter()
+            .map(InputBinding::Key)
+            .chain(buttons.into_iter().map(InputBinding::GamepadButton))
+            .chain(axes.into_iter().flat_map(|axis| {
+                [AxisDirection::Negative, AxisDirection::Positive].map(|direction| InputBinding::GamepadAxis(axis, direction))
+            }))
+            .map(|binding| binding.label())
+            .collect();
+
+        for language in crate::locale::LANGUAGES {
+            let contents = fs::read_to_string(assets.join(format!("{}.strings.ron", language))).unwrap();
+            let table: crate::locale::StringTable = ron::from_str(&contents).unwrap();
+            for label in &labels {
+                assert!(table.strings.contains_key(label), "{} is missing {}", language, label);
+            }
+        }
+    }
+
+    #[test]
+    fn rebinding_keeps_alternates() {
+        let mut bindings = ActionBindings::default();
+        bindings.rebind(Action::Jump, InputBinding::Key(KeyCode::KeyW));
+        assert_eq!(bindings.get(Action::Jump), &[
+            InputBinding::Key(KeyCode::KeyW),
+            InputBinding::Key(KeyCode::ArrowUp),
+            InputBinding::GamepadButton(GamepadButton::South),
+        ]);
+
+        bindings.rebind(Action::Jump, InputBinding::GamepadButton(GamepadButton::East));
+        assert_eq!(bindings.get(Action::Jump), &[
+            InputBinding::Key(KeyCode::KeyW),
+            InputBinding::Key(KeyCode::ArrowUp),
+            InputBinding::GamepadButton(GamepadButton::East),
+        ]);
+
+        // An alternate becomes the primary instead of showing up twice
+        bindings.rebind(Action::Jump, InputBinding::Key(KeyCode::ArrowUp));
+        assert_eq!(bindings.get(Action::Jump), &[
+            InputBinding::Key(KeyCode::ArrowUp),
+            InputBinding::GamepadButton(GamepadButton::East),
+        ]);
+    }
+}
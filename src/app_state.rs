@@ -0,0 +1,317 @@
+use bevy::{
+    asset::{RecursiveDependencyLoadState, UntypedAssetId},
+    prelude::*,
+};
+use bevy_ecs_ldtk::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::{
+    actions::{Action, InputLock, Menu},
+    game_flow::restart_world,
+    health::Health,
+    locale::Localizer,
+    player::Player,
+};
+
+/// Where the game as a whole is. Gameplay systems only run in [AppState::Playing].
+#[derive(States, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum AppState {
+    #[default]
+    TitleMenu,
+    /// Waiting for the LDtk projects and sprite sheets
+    Loading,
+    Playing,
+    Paused,
+    GameOver,
+}
+
+/// Images of the `#[sprite_sheet]` LDtk entity bundles and the UI, kept loaded so nothing pops in.
+/// Tilesets come in with the LDtk project itself.
+const SPRITE_SHEETS: [&str; 5] = [
+    "char_green_1.png",
+    "char_red_1.png",
+    "shop_anim.png",
+    "golem/golem_boss_sheet.png",
+    "icons_16x16.png",
+];
+
+/// Held for the whole game, besides the LDtk projects
+#[derive(Debug, Default, Resource)]
+pub struct LoadingAssets {
+    pub handles: Vec<UntypedHandle>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MenuChoice {
+    NewGame,
+    Resume,
+    Retry,
+    QuitToTitle,
+    Exit,
+}
+
+impl MenuChoice {
+    pub fn name_key(&self) -> &'static str {
+        match self {
+            MenuChoice::NewGame => "menu.new_game",
+            MenuChoice::Resume => "menu.resume",
+            MenuChoice::Retry => "menu.retry",
+            MenuChoice::QuitToTitle => "menu.quit_to_title",
+            MenuChoice::Exit => "menu.exit",
+        }
+    }
+}
+
+/// The title, pause and game over menus. Each lives only as long as its state.
+#[derive(Component)]
+pub struct StateMenu {
+    pub title: &'static str,
+    pub choices: Vec<MenuChoice>,
+    pub selected: usize,
+}
+
+/// Whether rapier was running before pausing, level transitions stop it too
+#[derive(Debug, Default, Resource)]
+pub struct SuspendedPhysics(Option<bool>);
+
+/// The menu that had the controls before the pause or game over screen took them
+#[derive(Debug, Default, Resource)]
+pub struct SuspendedMenu(Option<Menu>);
+
+fn spawn_state_menu(commands: &mut Commands, state: AppState, background: Color, menu: StateMenu) {
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.),
+                height: Val::Percent(100.),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            BackgroundColor(background),
+            // Over the HUD, map and other menus
+            GlobalZIndex(20),
+            StateScoped(state),
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new(""),
+                TextFont {
+                    font_size: 24.0,
+                    ..Default::default()
+                },
+                TextColor(Color::WHITE),
+                menu,
+            ));
+        });
+}
+
+pub fn spawn_title_menu(mut commands: Commands) {
+    spawn_state_menu(&mut commands, AppState::TitleMenu, Color::srgb(0.05, 0.05, 0.08), StateMenu {
+        title: "menu.title",
+        choices: vec![MenuChoice::NewGame, MenuChoice::Exit],
+        selected: 0,
+    });
+}
+
+pub fn load_sprite_sheets(mut loading: ResMut<LoadingAssets>, asset_server: Res<AssetServer>) {
+    loading.handles = SPRITE_SHEETS
+        .iter()
+        .map(|path| asset_server.load::<Image>(*path).untyped())
+        .collect();
+}
+
+pub fn spawn_loading_screen(mut commands: Commands) {
+    spawn_state_menu(&mut commands, AppState::Loading, Color::srgb(0.05, 0.05, 0.08), StateMenu {
+        title: "menu.loading",
+        choices: Vec::new(),
+        selected: 0,
+    });
+}
+
+pub fn spawn_pause_menu(mut commands: Commands) {
+    spawn_state_menu(&mut commands, AppState::Paused, Color::srgba(0., 0., 0., 0.6), StateMenu {
+        title: "menu.paused",
+        choices: vec![MenuChoice::Resume, MenuChoice::QuitToTitle],
+        selected: 0,
+    });
+}
+
+pub fn spawn_game_over_menu(mut commands: Commands) {
+    spawn_state_menu(&mut commands, AppState::GameOver, Color::srgba(0.2, 0., 0., 0.7), StateMenu {
+        title: "menu.game_over",
+        choices: vec![MenuChoice::Retry, MenuChoice::QuitToTitle],
+        selected: 0,
+    });
+}
+
+/// Moves on to playing once every project, its tilesets and the sprite sheets are in.
+/// If any of them fail the loading screen says so and offers to go back.
+pub fn finish_loading(
+    asset_server: Res<AssetServer>,
+    loading: Res<LoadingAssets>,
+    ldtk_projects: Query<&LdtkProjectHandle>,
+    mut menu_query: Query<&mut StateMenu>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    let projects = ldtk_projects.iter().map(|handle| AssetId::<LdtkProject>::from(handle).untyped());
+    let ids: Vec<UntypedAssetId> = projects.chain(loading.handles.iter().map(UntypedHandle::id)).collect();
+
+    let failed: Vec<String> = ids
+        .iter()
+        .filter(|id| {
+            matches!(asset_server.get_recursive_dependency_load_state(**id), Some(RecursiveDependencyLoadState::Failed(_)))
+        })
+        .map(|id| asset_server.get_path(*id).map_or_else(|| format!("{:?}", id), |path| path.to_string()))
+        .collect();
+    if !failed.is_empty() {
+        for mut menu in menu_query.iter_mut() {
+            if menu.title != "menu.load_failed" {
+                error!("Failed to load {}", failed.join(", "));
+                menu.title = "menu.load_failed";
+                menu.choices = vec![MenuChoice::QuitToTitle];
+            }
+        }
+        return;
+    }
+
+    if !ids.is_empty() && ids.iter().all(|id| asset_server.is_loaded_with_dependencies(*id)) {
+        info!("Loaded {} LDtk projects and {} sprite sheets", ldtk_projects.iter().len(), loading.handles.len());
+        next_state.set(AppState::Playing);
+    }
+}
+
+pub fn toggle_pause(
+    actions: Res<ButtonInput<Action>>,
+    lock: Res<InputLock>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    // Other menus and cutscenes have to finish first
+    if actions.just_pressed(Action::Pause) && lock.menu.is_none() && !lock.cutscene {
+        next_state.set(AppState::Paused);
+    }
+}
+
+pub fn detect_player_death(
+    player_query: Query<&Health, (With<Player>, Changed<Health>)>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    if player_query.iter().any(Health::is_dead) {
+        info!("Player died");
+        next_state.set(AppState::GameOver);
+    }
+}
+
+/// Stops physics and time for the pause and game over screens
+pub fn suspend_game(
+    mut suspended: ResMut<SuspendedPhysics>,
+    mut suspended_menu: ResMut<SuspendedMenu>,
+    mut rapier_config: Query<&mut RapierConfiguration>,
+    mut virtual_time: ResMut<Time<Virtual>>,
+    mut lock: ResMut<InputLock>,
+) {
+    for mut config in rapier_config.iter_mut() {
+        suspended.0 = Some(config.physics_pipeline_active);
+        config.physics_pipeline_active = false;
+    }
+    virtual_time.pause();
+    // Dying can interrupt the map, which gets the controls back afterwards
+    suspended_menu.0 = lock.menu.replace(Menu::Pause);
+}
+
+pub fn resume_game(
+    mut suspended: ResMut<SuspendedPhysics>,
+    mut suspended_menu: ResMut<SuspendedMenu>,
+    mut rapier_config: Query<&mut RapierConfiguration>,
+    mut virtual_time: ResMut<Time<Virtual>>,
+    mut lock: ResMut<InputLock>,
+) {
+    let active = suspended.0.take().unwrap_or(true);
+    for mut config in rapier_config.iter_mut() {
+        config.physics_pipeline_active = active;
+    }
+    virtual_time.unpause();
+    lock.menu = suspended_menu.0.take();
+}
+
+pub fn navigate_state_menus(
+    mut commands: Commands,
+    actions: Res<ButtonInput<Action>>,
+    state: Res<State<AppState>>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut menu_query: Query<&mut StateMenu>,
+    ldtk_projects: Query<Entity, With<LdtkProjectHandle>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let Ok(mut menu) = menu_query.get_single_mut() else { return };
+    if *state.get() == AppState::Paused && (actions.just_pressed(Action::Pause) || actions.just_pressed(Action::MenuBack)) {
+        next_state.set(AppState::Playing);
+        return;
+    }
+    if menu.choices.is_empty() {
+        return;
+    }
+
+    let len = menu.choices.len();
+    if actions.just_pressed(Action::MenuUp) {
+        menu.selected = (menu.selected + len - 1) % len;
+    }
+    if actions.just_pressed(Action::MenuDown) {
+        menu.selected = (menu.selected + 1) % len;
+    }
+    if !actions.just_pressed(Action::MenuConfirm) {
+        return;
+    }
+
+    match menu.choices[menu.selected] {
+        MenuChoice::NewGame => next_state.set(AppState::Loading),
+        MenuChoice::Resume => next_state.set(AppState::Playing),
+        MenuChoice::Retry => {
+            restart_world(&mut commands, &ldtk_projects);
+            next_state.set(AppState::Playing);
+        }
+        MenuChoice::QuitToTitle => next_state.set(AppState::TitleMenu),
+        MenuChoice::Exit => {
+            exit.send(AppExit::Success);
+        }
+    }
+}
+
+pub fn update_state_menus(localizer: Localizer, mut menu_query: Query<(Ref<StateMenu>, &mut Text)>) {
+    for (menu, mut text) in menu_query.iter_mut() {
+        if !menu.is_changed() && !localizer.changed() {
+            continue;
+        }
+        let mut lines = vec![localizer.get(menu.title), String::new()];
+        for (index, choice) in menu.choices.iter().enumerate() {
+            let cursor = if index == menu.selected { ">" } else { " " };
+            lines.push(format!("{} {}", cursor, localizer.get(choice.name_key())));
+        }
+        text.0 = lines.join("\n");
+    }
+}
+
+pub struct AppStatePlugin;
+
+impl Plugin for AppStatePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_state::<AppState>()
+            .enable_state_scoped_entities::<AppState>()
+            .init_resource::<SuspendedPhysics>()
+            .init_resource::<SuspendedMenu>()
+            .init_resource::<LoadingAssets>()
+            .add_systems(OnEnter(AppState::TitleMenu), spawn_title_menu)
+            .add_systems(OnEnter(AppState::Loading), (spawn_loading_screen, load_sprite_sheets))
+            .add_systems(OnEnter(AppState::Paused), (spawn_pause_menu, suspend_game))
+            .add_systems(OnExit(AppState::Paused), resume_game)
+            .add_systems(OnEnter(AppState::GameOver), (spawn_game_over_menu, suspend_game))
+            .add_systems(OnExit(AppState::GameOver), resume_game)
+            .add_systems(Update, (
+                finish_loading.run_if(in_state(AppState::Loading)),
+                (toggle_pause, detect_player_death).run_if(in_state(AppState::Playing)),
+                (navigate_state_menus, update_state_menus).chain(),
+            ));
+    }
+}
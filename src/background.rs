@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext},
+    ecs::system::SystemParam,
+    prelude::*,
+};
+use bevy_ecs_ldtk::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    camera::{camera_follow_player, FollowCamera},
+    game_flow::LdtkProjects,
+};
+
+const BACKGROUNDS_PATH: &str = "backgrounds/main.backgrounds.ron";
+
+/// Behind everything LDtk spawns
+const BACKGROUND_Z: f32 = -100.;
+/// Layers are drawn this much taller than the view so vertical parallax has room to move
+const OVERSCAN: f32 = 1.2;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LayerDef {
+    pub image: String,
+    /// How much the layer moves with the world, 0 sticks to the screen and 1 scrolls like the level
+    pub factor: Vec2,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BackgroundDef {
+    /// Back to front
+    pub layers: Vec<LayerDef>,
+}
+
+/// Every parallax background, loaded from `assets/backgrounds/main.backgrounds.ron`.
+/// Levels pick one by name with their `background` field.
+#[derive(Debug, Asset, TypePath, Deserialize)]
+pub struct Backgrounds {
+    /// Used by levels that don't set a background
+    pub default: String,
+    pub backgrounds: HashMap<String, BackgroundDef>,
+}
+
+#[derive(Default)]
+pub struct BackgroundsLoader;
+
+impl AssetLoader for BackgroundsLoader {
+    type Asset = Backgrounds;
+    type Settings = ();
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Backgrounds, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        Ok(ron::de::from_bytes(&bytes)?)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["backgrounds.ron"]
+    }
+}
+
+#[derive(Debug, Default, Resource)]
+pub struct BackgroundsHandle(pub Handle<Backgrounds>);
+
+/// The background the current level asked for, once its layers are spawned
+#[derive(Debug, Default, Resource)]
+pub struct CurrentBackground(pub Option<String>);
+
+#[derive(Debug, Component)]
+pub struct BackgroundLayer {
+    pub factor: Vec2,
+}
+
+/// Centre and scale of a layer for a camera looking at `center`.
+/// Only depends on where the camera is right now, not on how it got there,
+/// so layers can't drift however often the level switches.
+pub fn layer_placement(center: Vec2, view: Vec2, image: Vec2, factor: Vec2) -> (Vec2, f32) {
+    let scale = view.y * OVERSCAN / image.y;
+    let size = image * scale;
+
+    // The image repeats sideways, so shifting by a whole image width looks the same
+    let x = center.x - (center.x * factor.x).rem_euclid(size.x);
+    // Vertically there's only the overscan to play with
+    let margin = (size.y - view.y) / 2.;
+    let y = center.y - (center.y * factor.y).clamp(-margin, margin);
+    (Vec2::new(x, y), scale)
+}
+
+/// Copies of an image `width` wide side by side that cover a `view` wide camera wherever the layer wraps.
+/// The layer's centre stays up to one image width left of the camera's, so that much extra is needed on each side.
+/// Always odd, so one copy stays centred and a resize doesn't shift the pattern.
+pub fn layer_tiles(view: f32, width: f32) -> f32 {
+    2. * ((view / width + 1.) / 2.).ceil() + 1.
+}
+
+pub fn load_backgrounds(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(BackgroundsHandle(asset_server.load(BACKGROUNDS_PATH)));
+}
+
+/// Finds the level whose background should be showing
+#[derive(SystemParam)]
+pub struct BackgroundLevel<'w, 's> {
+    level_selection: Res<'w, LevelSelection>,
+    ldtk_projects: LdtkProjects<'w, 's>,
+}
+
+/// Swaps the layers when the player reaches a level with a different background
+pub fn select_background(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    backgrounds_handle: Res<BackgroundsHandle>,
+    backgrounds: Res<Assets<Backgrounds>>,
+    levels: BackgroundLevel,
+    mut current: ResMut<CurrentBackground>,
+    layer_query: Query<Entity, With<BackgroundLayer>>,
+) {
+    let Some(backgrounds) = backgrounds.get(&backgrounds_handle.0) else { return };
+    let Some(level) = levels.ldtk_projects.selected_level(&levels.level_selection) else { return };
+
+    let name = match level.get_maybe_string_field("background") {
+        Ok(Some(name)) => name.clone(),
+        _ => backgrounds.default.clone(),
+    };
+    if current.0.as_ref() == Some(&name) {
+        return;
+    }
+    current.0 = Some(name.clone());
+
+    let background = backgrounds.backgrounds.get(&name).or_else(|| {
+        warn!("{} wants background {:?}, which isn't in {}", level.identifier, name, BACKGROUNDS_PATH);
+        backgrounds.backgrounds.get(&backgrounds.default)
+    });
+
+    for entity in layer_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    let Some(background) = background else { return };
+    for (i, layer) in background.layers.iter().enumerate() {
+        commands.spawn((
+            BackgroundLayer { factor: layer.factor },
+            Sprite {
+                image: asset_server.load(&layer.image),
+                image_mode: SpriteImageMode::Tiled {
+                    tile_x: true,
+                    tile_y: false,
+                    stretch_value: 1.,
+                },
+                ..Default::default()
+            },
+            Transform::from_xyz(0., 0., BACKGROUND_Z + i as f32),
+            // Shown once it's been placed, so it doesn't flash at the origin
+            Visibility::Hidden,
+        ));
+    }
+}
+
+pub fn place_background_layers(
+    camera_query: Query<(&Transform, &FollowCamera), Without<BackgroundLayer>>,
+    images: Res<Assets<Image>>,
+    mut layer_query: Query<(&BackgroundLayer, &mut Sprite, &mut Transform, &mut Visibility)>,
+) {
+    let Ok((camera_transform, camera)) = camera_query.get_single() else { return };
+    if camera.view == Vec2::ZERO {
+        return;
+    }
+    // The camera's translation is the bottom left corner of the view
+    let center = camera_transform.translation.truncate() + camera.view / 2.;
+
+    for (layer, mut sprite, mut transform, mut visibility) in layer_query.iter_mut() {
+        let Some(image) = images.get(&sprite.image) else { continue };
+        let image_size = image.size().as_vec2();
+        let (position, scale) = layer_placement(center, camera.view, image_size, layer.factor);
+
+        let tiles = layer_tiles(camera.view.x, image_size.x * scale);
+        let size = Some(Vec2::new(image_size.x * tiles, image_size.y));
+        if sprite.custom_size != size {
+            sprite.custom_size = size;
+        }
+        transform.translation.x = position.x;
+        transform.translation.y = position.y;
+        transform.scale = Vec3::new(scale, scale, 1.);
+        *visibility = Visibility::Inherited;
+    }
+}
+
+pub struct BackgroundPlugin;
+
+impl Plugin for BackgroundPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_asset::<Backgrounds>()
+            .init_asset_loader::<BackgroundsLoader>()
+            .init_resource::<CurrentBackground>()
+            .add_systems(Startup, load_backgrounds)
+            .add_systems(Update, (select_background, place_background_layers)
+                .chain()
+                .after(camera_follow_player));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layers_wrap_with_the_camera() {
+        let view = Vec2::new(480., 270.);
+        let image = Vec2::new(320., 180.);
+
+        let (position, _) = layer_placement(Vec2::new(1234., -56.), view, image, Vec2::ZERO);
+        assert_eq!(position, Vec2::new(1234., -56.));
+
+        // At half speed the layer looks the same again after the camera moves two image widths
+        let (_, scale) = layer_placement(Vec2::ZERO, view, image, Vec2::splat(0.5));
+        let offset = |x: f32| layer_placement(Vec2::new(x, 0.), view, image, Vec2::splat(0.5)).0.x - x;
+        assert!((offset(100.) - offset(100. + image.x * scale * 2.)).abs() < 0.01);
+    }
+
+    #[test]
+    fn layers_cover_wide_views() {
+        let image = Vec2::new(320., 180.);
+        for view in [Vec2::new(480., 270.), Vec2::new(630., 270.), Vec2::new(1280., 270.)] {
+            let (_, scale) = layer_placement(Vec2::ZERO, view, image, Vec2::ONE);
+            let width = image.x * scale;
+            let tiles = layer_tiles(view.x, width);
+            assert_eq!(tiles % 2., 1.);
+            // Worst case the layer's centre is a whole image width left of the camera's
+            assert!(tiles * width / 2. >= view.x / 2. + width, "{} copies leave a gap at {}", tiles, view);
+        }
+        assert_eq!(layer_tiles(480., 576.), 3.);
+    }
+
+    #[test]
+    fn bundled_backgrounds_parse() {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets").join(BACKGROUNDS_PATH);
+        let backgrounds: Backgrounds = ron::de::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        assert!(backgrounds.backgrounds.contains_key(&backgrounds.default));
+    }
+}
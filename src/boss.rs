@@ -1,11 +1,44 @@
-use bevy::prelude::*;
+use bevy::{ecs::system::SystemParam, prelude::*};
 use bevy_ecs_ldtk::{prelude::*, utils::ldtk_pixel_coords_to_translation_pivoted};
-use bevy_rapier2d::prelude::Velocity;
+use bevy_rapier2d::prelude::*;
 
-use crate::{colliders::GolemColliderBundle, dialogue::Dialogue, health::Health, items::{ItemType, Loot}};
+use crate::{
+    actions::InputLock,
+    app_state::AppState,
+    camera::CameraFocus,
+    camera_effects::{CameraKick, CameraShake, HitStop},
+    colliders::{GolemColliderBundle, PLAYER_GROUP},
+    dialogue::{Dialogue, DialogueEnded, DialogueHandle, StartDialogue},
+    flags::GameFlags,
+    health::Health,
+    items::{ItemType, Loot},
+    locale::LocalizedText,
+    player::Player,
+    state_machine::{AttackType, PlayerEvent},
+    world_state::{EntityState, WorldState},
+};
+
+/// How far the arena reaches past either end of the golem's patrol
+const ARENA_MARGIN: f32 = 72.;
+const ARENA_HALF_HEIGHT: f32 = 120.;
+const GATE_HALF_WIDTH: f32 = 6.;
+
+/// How long the camera takes to pan over to the boss
+const INTRO_PAN_SECONDS: f32 = 1.5;
+
+const MELEE_RANGE: f32 = 64.;
+const MELEE_DAMAGE: i32 = 25;
+
+const DEFEATED_GOLEM_FLAG: &str = "defeated:golem";
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct Golem;
 
 #[derive(Default, Bundle, LdtkEntity)]
 pub struct GolemBundle {
+    pub golem: Golem,
+    #[with(golem_name)]
+    pub name: Name,
     #[sprite_sheet("golem/golem_boss_sheet.png", 99, 99, 10, 10, 0, 0, 0)]
     pub sprite_sheet: Sprite,
     #[from_entity_instance]
@@ -20,6 +53,11 @@ pub struct GolemBundle {
     pub patrol: Patrol,
 }
 
+// Dialogue speakers are matched by name, the Golem doesn't have a name field in LDtk
+fn golem_name(_: &EntityInstance) -> Name {
+    Name::new("Golem")
+}
+
 #[derive(Clone, PartialEq, Debug, Default, Component)]
 pub struct Patrol {
     pub points: Vec<Vec2>,
@@ -44,9 +82,8 @@ impl LdtkEntity for Patrol {
             entity_instance.pivot,
         ));
 
-        let ldtk_patrol_points = entity_instance
-            .iter_points_field("patrol")
-            .expect("patrol field should be correclty typed");
+        // Without a usable patrol field it just stands where it was placed
+        let ldtk_patrol_points = entity_instance.iter_points_field("patrol").into_iter().flatten();
 
         for ldtk_point in ldtk_patrol_points {
             // The +1 is necessary here due to the pivot of the entities in the sample
@@ -106,13 +143,393 @@ pub fn patrol(mut query: Query<(&mut Transform, &mut Velocity, &mut Patrol)>) {
     }
 }
 
+/// Sent to wake the boss up, e.g. by a dialogue effect
+#[derive(Event, Debug, Clone)]
+pub struct StartBossFight;
+
+/// Sent when a boss or enemy dies, with its `Name`
+#[derive(Event, Debug, Clone)]
+pub struct EnemyDefeated {
+    pub name: String,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum EncounterPhase {
+    /// The player hasn't reached the arena yet
+    #[default]
+    Waiting,
+    /// Input is locked while the camera pans over to the boss
+    Intro,
+    Dialogue,
+    Fighting,
+    Defeated,
+}
+
+#[derive(Debug, Default, Resource)]
+pub struct BossFight {
+    pub phase: EncounterPhase,
+    pub boss: Option<Entity>,
+    /// Health the boss started the fight with, for the health bar
+    pub max_health: i32,
+    pub pan: Timer,
+}
+
+/// Sensor around the golem's patrol route, walking into it starts the encounter
+#[derive(Component)]
+pub struct BossArena {
+    pub half_size: Vec2,
+}
+
+/// Keeps the player inside the arena until the boss is beaten
+#[derive(Component)]
+pub struct ArenaGate;
+
+#[derive(Component)]
+pub struct BossHealthBar;
+
+#[derive(Component)]
+pub struct BossHealthFill;
+
+#[derive(Component)]
+pub struct BossNameText;
+
+pub fn spawn_boss_arena(
+    mut commands: Commands,
+    golem_query: Query<(Entity, &Parent, &Patrol), Added<Golem>>,
+    mut boss_fight: ResMut<BossFight>,
+    flags: Res<GameFlags>,
+) {
+    for (golem, parent, patrol) in golem_query.iter() {
+        let Some(first) = patrol.points.first() else { continue };
+        let (min_x, max_x) = patrol
+            .points
+            .iter()
+            .fold((first.x, first.x), |(min, max), point| (min.min(point.x), max.max(point.x)));
+        let half_size = Vec2::new((max_x - min_x) / 2. + ARENA_MARGIN, ARENA_HALF_HEIGHT);
+
+        // Siblings of the golem so the patrol points line up with the arena
+        commands.entity(parent.get()).with_children(|parent| {
+            parent.spawn((
+                BossArena { half_size },
+                Transform::from_xyz((min_x + max_x) / 2., first.y, 0.),
+                Collider::cuboid(half_size.x, half_size.y),
+                Sensor,
+                ActiveEvents::COLLISION_EVENTS,
+                CollisionGroups::new(PLAYER_GROUP, PLAYER_GROUP),
+            ));
+        });
+
+        // A respawned golem starts over, unless it was already beaten
+        *boss_fight = BossFight {
+            phase: if flags.get_bool(DEFEATED_GOLEM_FLAG) {
+                EncounterPhase::Defeated
+            } else {
+                EncounterPhase::Waiting
+            },
+            boss: Some(golem),
+            ..Default::default()
+        };
+    }
+}
+
+pub fn enter_boss_arena(
+    mut collision_events: EventReader<CollisionEvent>,
+    player_query: Query<(), With<Player>>,
+    arena_query: Query<(), With<BossArena>>,
+    mut boss_fight: ResMut<BossFight>,
+    mut lock: ResMut<InputLock>,
+) {
+    for event in collision_events.read() {
+        let CollisionEvent::Started(e1, e2, _) = event else { continue };
+        let entered = (player_query.contains(*e1) && arena_query.contains(*e2))
+            || (player_query.contains(*e2) && arena_query.contains(*e1));
+
+        if entered && boss_fight.phase == EncounterPhase::Waiting {
+            boss_fight.phase = EncounterPhase::Intro;
+            boss_fight.pan = Timer::from_seconds(INTRO_PAN_SECONDS, TimerMode::Once);
+            lock.cutscene = true;
+        }
+    }
+}
+
+/// What the intro cutscene takes over until the fight starts
+#[derive(SystemParam)]
+pub struct Encounter<'w> {
+    boss_fight: ResMut<'w, BossFight>,
+    focus: ResMut<'w, CameraFocus>,
+    lock: ResMut<'w, InputLock>,
+}
+
+/// Pans the camera over to the boss, then lets it speak
+pub fn play_boss_intro(
+    time: Res<Time>,
+    mut encounter: Encounter,
+    player_query: Query<&GlobalTransform, With<Player>>,
+    boss_query: Query<(&GlobalTransform, &Name, Option<&DialogueHandle>)>,
+    mut start_dialogue: EventWriter<StartDialogue>,
+    mut start_fight: EventWriter<StartBossFight>,
+) {
+    if !matches!(encounter.boss_fight.phase, EncounterPhase::Intro | EncounterPhase::Dialogue) {
+        return;
+    }
+    let Some((boss_transform, name, dialogue)) = encounter.boss_fight.boss.and_then(|boss| boss_query.get(boss).ok()) else {
+        return;
+    };
+    let boss_position = boss_transform.translation().truncate();
+
+    if encounter.boss_fight.phase == EncounterPhase::Dialogue {
+        encounter.focus.target = Some(boss_position);
+        return;
+    }
+
+    let Ok(player_transform) = player_query.get_single() else { return };
+    encounter.boss_fight.pan.tick(time.delta());
+    let t = encounter.boss_fight.pan.fraction();
+    let eased = t * t * (3. - 2. * t);
+    encounter.focus.target = Some(player_transform.translation().truncate().lerp(boss_position, eased));
+
+    if encounter.boss_fight.pan.finished() {
+        // Dialogue takes over the input lock from here
+        encounter.lock.cutscene = false;
+        match dialogue {
+            Some(dialogue) => {
+                encounter.boss_fight.phase = EncounterPhase::Dialogue;
+                start_dialogue.send(StartDialogue {
+                    graph: dialogue.0.clone(),
+                    speaker: name.to_string(),
+                });
+            }
+            None => {
+                start_fight.send(StartBossFight);
+            }
+        }
+    }
+}
+
+pub fn finish_boss_intro(
+    mut ended: EventReader<DialogueEnded>,
+    boss_fight: Res<BossFight>,
+    mut start_fight: EventWriter<StartBossFight>,
+) {
+    if ended.read().next().is_some() && boss_fight.phase == EncounterPhase::Dialogue {
+        start_fight.send(StartBossFight);
+    }
+}
+
+pub fn start_boss_fight(
+    mut commands: Commands,
+    mut events: EventReader<StartBossFight>,
+    mut encounter: Encounter,
+    boss_query: Query<(&Health, &Name)>,
+    arena_query: Query<(Entity, &BossArena)>,
+    mut name_query: Query<&mut LocalizedText, With<BossNameText>>,
+) {
+    if events.read().next().is_none()
+        || matches!(encounter.boss_fight.phase, EncounterPhase::Fighting | EncounterPhase::Defeated)
+    {
+        return;
+    }
+    let Some((health, name)) = encounter.boss_fight.boss.and_then(|boss| boss_query.get(boss).ok()) else {
+        return;
+    };
+
+    encounter.boss_fight.phase = EncounterPhase::Fighting;
+    encounter.boss_fight.max_health = health.current();
+    encounter.focus.target = None;
+    encounter.lock.cutscene = false;
+    for mut text in name_query.iter_mut() {
+        *text = LocalizedText::new(format!("name.{}", name));
+    }
+
+    // Close the arena on both sides
+    for (arena, BossArena { half_size }) in arena_query.iter() {
+        commands.entity(arena).with_children(|parent| {
+            for side in [-1., 1.] {
+                parent.spawn((
+                    ArenaGate,
+                    Sprite::from_color(
+                        Color::srgb(0.35, 0.3, 0.3),
+                        Vec2::new(GATE_HALF_WIDTH, half_size.y) * 2.,
+                    ),
+                    Transform::from_xyz(side * (half_size.x - GATE_HALF_WIDTH), 0., 0.),
+                    Collider::cuboid(GATE_HALF_WIDTH, half_size.y),
+                    RigidBody::Fixed,
+                ));
+            }
+        });
+    }
+}
+
+pub fn player_hits_boss(
+    mut player_events: EventReader<PlayerEvent>,
+    boss_fight: Res<BossFight>,
+    player_query: Query<&GlobalTransform, With<Player>>,
+    mut boss_query: Query<(&GlobalTransform, &mut Health), With<Golem>>,
+    mut shake: EventWriter<CameraShake>,
+    mut kick: EventWriter<CameraKick>,
+    mut hit_stop: EventWriter<HitStop>,
+) {
+    for event in player_events.read() {
+        if !matches!(event, PlayerEvent::AttackStarted(AttackType::Melee)) {
+            continue;
+        }
+        let Ok(player_transform) = player_query.get_single() else { continue };
+        let Some(boss) = boss_fight.boss else { continue };
+        let Ok((boss_transform, mut health)) = boss_query.get_mut(boss) else { continue };
+
+        let to_boss = (boss_transform.translation() - player_transform.translation()).truncate();
+        if to_boss.length() <= MELEE_RANGE {
+            health.damage(MELEE_DAMAGE);
+            shake.send(CameraShake { trauma: 0.3 });
+            kick.send(CameraKick { impulse: to_boss.normalize_or_zero() * 6. });
+            hit_stop.send(HitStop::heavy());
+        }
+    }
+}
+
+/// What remembers that the boss is gone
+#[derive(SystemParam)]
+pub struct DefeatRecords<'w> {
+    flags: ResMut<'w, GameFlags>,
+    world_state: ResMut<'w, WorldState>,
+}
+
+/// Opens the arena back up once the boss is out of health
+pub fn check_boss_defeat(
+    mut commands: Commands,
+    mut boss_fight: ResMut<BossFight>,
+    mut records: DefeatRecords,
+    boss_query: Query<(&Health, &Name, Option<&EntityIid>)>,
+    gate_query: Query<Entity, With<ArenaGate>>,
+    mut defeated: EventWriter<EnemyDefeated>,
+    mut shake: EventWriter<CameraShake>,
+) {
+    if boss_fight.phase != EncounterPhase::Fighting {
+        return;
+    }
+    let Some(boss) = boss_fight.boss else { return };
+    let Ok((health, name, iid)) = boss_query.get(boss) else { return };
+    if !health.is_dead() {
+        return;
+    }
+
+    boss_fight.phase = EncounterPhase::Defeated;
+    records.flags.set_bool(DEFEATED_GOLEM_FLAG, true);
+    shake.send(CameraShake { trauma: 1. });
+    defeated.send(EnemyDefeated {
+        name: name.to_string(),
+    });
+    if let Some(iid) = iid {
+        records.world_state.record(iid, EntityState::Defeated);
+    }
+    commands.entity(boss).despawn_recursive();
+    for gate in gate_query.iter() {
+        commands.entity(gate).despawn_recursive();
+    }
+}
+
+pub fn spawn_boss_health_bar(mut commands: Commands) {
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(20.0),
+                left: Val::Percent(25.0),
+                right: Val::Percent(25.0),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                row_gap: Val::Px(4.0),
+                ..Default::default()
+            },
+            Visibility::Hidden,
+            BossHealthBar,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new(""),
+                TextFont {
+                    font_size: 18.0,
+                    ..Default::default()
+                },
+                TextColor(Color::WHITE),
+                LocalizedText::default(),
+                BossNameText,
+            ));
+            parent
+                .spawn((
+                    Node {
+                        width: Val::Percent(100.0),
+                        height: Val::Px(12.0),
+                        ..Default::default()
+                    },
+                    BackgroundColor::from(Color::srgba(0.2, 0.2, 0.2, 0.8)),
+                ))
+                .with_children(|parent| {
+                    parent.spawn((
+                        Node {
+                            width: Val::Percent(100.0),
+                            height: Val::Percent(100.0),
+                            ..Default::default()
+                        },
+                        BackgroundColor::from(Color::srgb(0.75, 0.15, 0.15)),
+                        BossHealthFill,
+                    ));
+                });
+        });
+}
+
+pub fn update_boss_health_bar(
+    boss_fight: Res<BossFight>,
+    boss_query: Query<&Health>,
+    mut bar_query: Query<&mut Visibility, With<BossHealthBar>>,
+    mut fill_query: Query<&mut Node, With<BossHealthFill>>,
+) {
+    let health = boss_fight
+        .boss
+        .filter(|_| boss_fight.phase == EncounterPhase::Fighting)
+        .and_then(|boss| boss_query.get(boss).ok());
+
+    for mut visibility in bar_query.iter_mut() {
+        visibility.set_if_neq(if health.is_some() {
+            Visibility::Visible
+        } else {
+            Visibility::Hidden
+        });
+    }
+
+    let Some(health) = health else { return };
+    let fraction = health.current() as f32 / boss_fight.max_health.max(1) as f32;
+    for mut node in fill_query.iter_mut() {
+        node.width = Val::Percent(fraction * 100.0);
+    }
+}
+
+fn boss_fight_active(boss_fight: Res<BossFight>) -> bool {
+    boss_fight.phase == EncounterPhase::Fighting
+}
+
 pub struct BossesPlugin;
 
 impl Plugin for BossesPlugin {
     fn build(&self, app: &mut App) {
         app
             .register_type::<Loot>()
-            .add_systems(Update, patrol)
+            .init_resource::<BossFight>()
+            .add_event::<StartBossFight>()
+            .add_event::<EnemyDefeated>()
+            .add_systems(Startup, spawn_boss_health_bar)
+            .add_systems(Update, (
+                spawn_boss_arena,
+                enter_boss_arena,
+                play_boss_intro,
+                finish_boss_intro,
+                start_boss_fight,
+                // The golem stays put until something starts the fight
+                patrol.run_if(boss_fight_active),
+                player_hits_boss.run_if(boss_fight_active),
+                check_boss_defeat,
+                update_boss_health_bar,
+            ).chain().run_if(in_state(AppState::Playing)))
             .register_ldtk_entity::<GolemBundle>("Golem");
     }
 }
\ No newline at end of file
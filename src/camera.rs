@@ -1,71 +1,507 @@
-use bevy::prelude::*;
+use bevy::{
+    ecs::system::SystemParam,
+    prelude::*,
+    render::{
+        camera::RenderTarget,
+        render_resource::{Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages},
+        view::RenderLayers,
+    },
+    window::{PrimaryWindow, WindowRef},
+};
 use bevy_ecs_ldtk::prelude::*;
+use bevy_rapier2d::prelude::*;
 
-use crate::player::Player;
+use crate::{
+    camera_effects::CameraEffects, camera_zones::ActiveCameraZone, game_flow::LdtkProjects,
+    ground_detection::GroundDetection, level_transition::LevelTransition, player::Player,
+};
 
-const ASPECT_RATIO: f32 = 16. / 9.;
+/// Only the outer camera sees the canvas sprite
+const CANVAS_LAYER: RenderLayers = RenderLayers::layer(1);
 
+/// Windows narrower or wider than this get letterboxed in pixel-perfect mode
+const MIN_ASPECT_RATIO: f32 = 4. / 3.;
+const MAX_ASPECT_RATIO: f32 = 21. / 9.;
+
+/// A world position the camera looks at instead of the player, e.g. while a boss is introduced
+#[derive(Debug, Default, Resource)]
+pub struct CameraFocus {
+    pub target: Option<Vec2>,
+}
+
+/// Tuning for how the camera follows the player
+#[derive(Debug, Clone, Resource)]
+pub struct CameraSettings {
+    /// Half size of the box around the follow point the player can move in without the camera moving
+    pub deadzone: Vec2,
+    /// How far ahead of the player the camera looks in the direction they're facing
+    pub look_ahead: f32,
+    /// Extra look-ahead per unit of horizontal speed
+    pub look_ahead_per_speed: f32,
+    /// Look-ahead never goes past this
+    pub max_look_ahead: f32,
+    /// Roughly how long the camera takes to catch up, in seconds
+    pub smooth_time: f32,
+    /// How long the look-ahead takes to swing around when turning
+    pub look_ahead_smooth_time: f32,
+    /// Render the world at one texel per world pixel, scaled up to the window by a whole number
+    pub pixel_perfect: bool,
+    /// Roughly how many world pixels tall the view is in pixel-perfect mode.
+    /// The width follows the window's aspect ratio.
+    pub pixel_height: u32,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        CameraSettings {
+            deadzone: Vec2::new(24., 48.),
+            look_ahead: 32.,
+            look_ahead_per_speed: 0.15,
+            max_look_ahead: 80.,
+            smooth_time: 0.2,
+            look_ahead_smooth_time: 0.5,
+            pixel_perfect: true,
+            pixel_height: 270,
+        }
+    }
+}
+
+/// Follow state, kept on the camera
+#[derive(Debug, Default, Component)]
+pub struct FollowCamera {
+    /// The point the deadzone is centred on
+    pub follow: Option<Vec2>,
+    pub look_ahead: f32,
+    pub look_ahead_velocity: f32,
+    /// Where the camera is looking, before clamping to the level
+    pub center: Vec2,
+    pub velocity: Vec2,
+    /// Size of the view in world units, fitted to the current level
+    pub view: Vec2,
+    /// Centre of what's actually on screen, after clamping and transitions
+    pub shown: Vec2,
+}
+
+/// The low resolution image the world is drawn to in pixel-perfect mode
+#[derive(Debug, Default, Resource)]
+pub struct PixelCanvas {
+    pub image: Handle<Image>,
+    pub size: UVec2,
+    /// Window pixels per canvas pixel
+    pub scale: u32,
+    /// Whether the cameras are currently set up for pixel-perfect mode
+    pub applied: Option<bool>,
+    /// Canvas size outside camera zones
+    pub base_size: UVec2,
+    /// Canvas size that fits the active camera zone's view at a whole-number scale
+    pub zone_size: Option<UVec2>,
+    /// Whether the canvas is currently at `zone_size`
+    pub zoned: bool,
+}
+
+/// Shows the canvas on screen, scaled up
+#[derive(Component)]
+pub struct OuterCamera;
+
+#[derive(Component)]
+pub struct CanvasSprite;
+
+/// Canvas size and integer scale for a window. Whatever's left over is letterboxed.
+pub fn canvas_size(window: Vec2, pixel_height: u32) -> (UVec2, u32) {
+    // Tall windows scale by their width, so the canvas is still `pixel_height` tall at the narrowest aspect ratio
+    let min_width = pixel_height as f32 * MIN_ASPECT_RATIO;
+    let scale = ((window.y / pixel_height as f32).min(window.x / min_width).floor() as u32).max(1);
+    (canvas_at_scale(window, scale), scale)
+}
+
+/// Canvas size and the biggest whole-number scale that still shows all of `view`
+pub fn canvas_fitting(window: Vec2, view: Vec2) -> (UVec2, u32) {
+    let scale = ((window / view).min_element().floor() as u32).max(1);
+    (canvas_at_scale(window, scale), scale)
+}
+
+fn canvas_at_scale(window: Vec2, scale: u32) -> UVec2 {
+    let height = (window.y / scale as f32).floor().max(1.);
+    // Never wider than the window, only wide ones get bars on the sides
+    let width = (window.x / scale as f32).floor().max(1.).min((height * MAX_ASPECT_RATIO).floor());
+    // Too narrow, so give up some height instead
+    let height = height.min((width / MIN_ASPECT_RATIO).round());
+    UVec2::new(width as u32, height as u32)
+}
+
+/// Critically damped spring towards `target`, like Unity's `SmoothDamp`.
+/// Gets there in roughly `smooth_time` without overshooting.
+pub fn smooth_damp(current: f32, target: f32, velocity: &mut f32, smooth_time: f32, delta: f32) -> f32 {
+    let omega = 2. / smooth_time.max(0.0001);
+    let x = omega * delta;
+    let exp = 1. / (1. + x + 0.48 * x * x + 0.235 * x * x * x);
+    let change = current - target;
+    let temp = (*velocity + omega * change) * delta;
+    *velocity = (*velocity - omega * temp) * exp;
+    let mut result = target + (change + temp) * exp;
+
+    // Don't overshoot
+    if (target - current > 0.) == (result > target) {
+        result = target;
+        *velocity = (result - target) / delta.max(0.0001);
+    }
+    result
+}
+
+/// Moves `follow` just enough to keep `position` inside the deadzone on one axis
+fn push_deadzone(follow: f32, position: f32, half_size: f32) -> f32 {
+    follow.clamp(position - half_size, position + half_size)
+}
+
+/// Centres the view on `center` without showing anything outside the level,
+/// or centres on the level if it's smaller than the view
+fn clamp_to_level(center: f32, view: f32, min: f32, size: f32) -> f32 {
+    if size <= view {
+        min + size / 2.
+    } else {
+        center.clamp(min + view / 2., min + size - view / 2.)
+    }
+}
+
+pub fn spawn_pixel_canvas(mut commands: Commands, mut images: ResMut<Assets<Image>>, settings: Res<CameraSettings>) {
+    let (size, scale) = canvas_size(Vec2::new(1280., 720.), settings.pixel_height);
+    let mut image = Image {
+        texture_descriptor: TextureDescriptor {
+            label: Some("pixel_canvas"),
+            size: Extent3d::default(),
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Bgra8UnormSrgb,
+            mip_level_count: 1,
+            sample_count: 1,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST | TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        },
+        ..Default::default()
+    };
+    image.resize(Extent3d {
+        width: size.x,
+        height: size.y,
+        ..Default::default()
+    });
+    let image = images.add(image);
+
+    commands.spawn((Sprite::from_image(image.clone()), CANVAS_LAYER, CanvasSprite));
+    commands.spawn((
+        Camera2d,
+        Camera {
+            // After the world camera has drawn the canvas, with black bars around it
+            order: 1,
+            clear_color: ClearColorConfig::Custom(Color::BLACK),
+            ..Default::default()
+        },
+        Msaa::Off,
+        CANVAS_LAYER,
+        OuterCamera,
+    ));
+    commands.insert_resource(PixelCanvas {
+        image,
+        size,
+        scale,
+        applied: None,
+        base_size: size,
+        ..Default::default()
+    });
+}
+
+/// What the canvas has to fit in pixel-perfect mode
+#[derive(SystemParam)]
+pub struct CanvasFit<'w, 's> {
+    settings: Res<'w, CameraSettings>,
+    window_query: Query<'w, 's, &'static Window, With<PrimaryWindow>>,
+    active_zone: Res<'w, ActiveCameraZone>,
+}
+
+impl CanvasFit<'_, '_> {
+    /// Canvas size and scale for the window outside camera zones, and the ones that fit the active zone
+    fn canvas(&self, window: &Window) -> ((UVec2, u32), Option<(UVec2, u32)>) {
+        let base = canvas_size(window.size(), self.settings.pixel_height);
+        let zone = self.active_zone.zone.as_ref().map(|(zone, _)| canvas_fitting(window.size(), zone.view(base.0.as_vec2())));
+        (base, zone)
+    }
+}
+
+/// Keeps the canvas matched to the window, and switches the world camera
+/// between the canvas and the window when pixel-perfect mode is toggled
 #[allow(clippy::type_complexity)]
-pub fn camera_fit_inside_current_level(
+pub fn fit_canvas(
+    mut commands: Commands,
+    fit: CanvasFit,
+    mut canvas: ResMut<PixelCanvas>,
+    mut images: ResMut<Assets<Image>>,
+    mut world_camera_query: Query<(Entity, &mut Camera), (With<FollowCamera>, Without<OuterCamera>)>,
+    mut outer_camera_query: Query<(Entity, &mut Camera, &mut OrthographicProjection), With<OuterCamera>>,
+) {
+    let Ok(window) = fit.window_query.get_single() else { return };
+    let Ok((world_entity, mut world_camera)) = world_camera_query.get_single_mut() else { return };
+    let Ok((outer_entity, mut outer_camera, mut outer_projection)) = outer_camera_query.get_single_mut() else {
+        return;
+    };
+
+    let pixel_perfect = fit.settings.pixel_perfect;
+    if canvas.applied != Some(pixel_perfect) {
+        world_camera.target = if pixel_perfect {
+            RenderTarget::Image(canvas.image.clone())
+        } else {
+            RenderTarget::Window(WindowRef::Primary)
+        };
+        outer_camera.is_active = pixel_perfect;
+        // UI is drawn at full resolution by whichever camera ends up on the window
+        let (ui_camera, other) = if pixel_perfect {
+            (outer_entity, world_entity)
+        } else {
+            (world_entity, outer_entity)
+        };
+        commands.entity(ui_camera).insert(IsDefaultUiCamera);
+        commands.entity(other).remove::<IsDefaultUiCamera>();
+        canvas.applied = Some(pixel_perfect);
+    }
+    if !pixel_perfect {
+        return;
+    }
+
+    let (base, zone) = fit.canvas(window);
+    // The scale only changes once the camera has blended all the way in or out of a zone.
+    // Until then `camera_follow_player` blends the view inside whichever canvas is current.
+    let blend = fit.active_zone.blend();
+    canvas.zoned = zone.is_some() && (blend >= 1. || (canvas.zoned && blend > 0.));
+    canvas.base_size = base.0;
+    canvas.zone_size = zone.map(|(size, _)| size);
+    let (size, scale) = match zone {
+        Some(zone) if canvas.zoned => zone,
+        _ => base,
+    };
+    if size != canvas.size {
+        if let Some(image) = images.get_mut(&canvas.image) {
+            image.resize(Extent3d {
+                width: size.x,
+                height: size.y,
+                ..Default::default()
+            });
+        }
+        canvas.size = size;
+    }
+    if scale != canvas.scale || outer_projection.scale != 1. / scale as f32 {
+        outer_projection.scale = 1. / scale as f32;
+        canvas.scale = scale;
+    }
+}
+
+/// Finds the level the camera should keep to
+#[allow(clippy::type_complexity)]
+#[derive(SystemParam)]
+pub struct CameraLevel<'w, 's> {
+    level_query: Query<'w, 's, (&'static Transform, &'static LevelIid), (Without<OrthographicProjection>, Without<Player>)>,
+    ldtk_projects: LdtkProjects<'w, 's>,
+    level_selection: Res<'w, LevelSelection>,
+}
+
+/// Everything besides the player that moves the camera
+#[derive(SystemParam)]
+pub struct CameraOverrides<'w> {
+    focus: Res<'w, CameraFocus>,
+    effects: Res<'w, CameraEffects>,
+    active_zone: Res<'w, ActiveCameraZone>,
+    level_transition: Res<'w, LevelTransition>,
+}
+
+/// How the camera follows and what it renders to
+#[derive(SystemParam)]
+pub struct CameraSetup<'w, 's> {
+    settings: Res<'w, CameraSettings>,
+    canvas: Res<'w, PixelCanvas>,
+    window_query: Query<'w, 's, &'static Window, With<PrimaryWindow>>,
+}
+
+#[allow(clippy::type_complexity)]
+pub fn camera_follow_player(
+    time: Res<Time>,
+    setup: CameraSetup,
     mut camera_query: Query<
         (
             &mut bevy::render::camera::OrthographicProjection,
             &mut Transform,
+            &mut FollowCamera,
         ),
         Without<Player>,
     >,
-    player_query: Query<&Transform, With<Player>>,
-    level_query: Query<(&Transform, &LevelIid), (Without<OrthographicProjection>, Without<Player>)>,
-    ldtk_projects: Query<&LdtkProjectHandle>,
-    level_selection: Res<LevelSelection>,
-    ldtk_project_assets: Res<Assets<LdtkProject>>,
+    player_query: Query<(&Transform, &Velocity, &GroundDetection, &Sprite), With<Player>>,
+    levels: CameraLevel,
+    overrides: CameraOverrides,
 ) {
-    if let Ok(Transform {
-        translation: player_translation,
-        ..
-    }) = player_query.get_single()
-    {
-        let player_translation = *player_translation;
-
-        let (mut orthographic_projection, mut camera_transform) = camera_query.single_mut();
-
-        for (level_transform, level_iid) in &level_query {
-            let ldtk_project = ldtk_project_assets
-                .get(ldtk_projects.single())
-                .expect("Project should be loaded if level has spawned");
-
-            let level = ldtk_project
-                .get_raw_level_by_iid(&level_iid.to_string())
-                .expect("Spawned level should exist in LDtk project");
-
-            if level_selection.is_match(&LevelIndices::default(), level) {
-                let level_ratio = level.px_wid as f32 / level.px_hei as f32;
-                orthographic_projection.viewport_origin = Vec2::ZERO;
-                if level_ratio > ASPECT_RATIO {
-                    // level is wider than the screen
-                    let height = (level.px_hei as f32 / 9.).round() * 9.;
-                    let width = height * ASPECT_RATIO;
-                    orthographic_projection.scaling_mode =
-                        bevy::render::camera::ScalingMode::Fixed { width, height };
-                    camera_transform.translation.x =
-                        (player_translation.x - level_transform.translation.x - width / 2.)
-                            .clamp(0., level.px_wid as f32 - width);
-                    camera_transform.translation.y = 0.;
-                } else {
-                    // level is taller than the screen
-                    let width = (level.px_wid as f32 / 16.).round() * 16.;
-                    let height = width / ASPECT_RATIO;
-                    orthographic_projection.scaling_mode =
-                        bevy::render::camera::ScalingMode::Fixed { width, height };
-                    camera_transform.translation.y =
-                        (player_translation.y - level_transform.translation.y - height / 2.)
-                            .clamp(0., level.px_hei as f32 - height);
-                    camera_transform.translation.x = 0.;
-                }
-
-                camera_transform.translation.x += level_transform.translation.x;
-                camera_transform.translation.y += level_transform.translation.y;
+    let Ok((player_transform, player_velocity, ground, sprite)) = player_query.get_single() else { return };
+    let Ok((mut orthographic_projection, mut camera_transform, mut camera)) = camera_query.get_single_mut() else {
+        return;
+    };
+
+    let current_level = levels.level_query.iter().find_map(|(level_transform, level_iid)| {
+        let level = levels.ldtk_projects.level_by_iid(&level_iid.to_string())?;
+        levels.level_selection
+            .is_match(&LevelIndices::default(), level)
+            .then_some((level_transform, level))
+    });
+    let Some((level_transform, level)) = current_level else { return };
+
+    let level_view = if setup.settings.pixel_perfect {
+        // One world pixel per canvas pixel, however big the level is
+        setup.canvas.base_size.as_vec2()
+    } else {
+        // Fit the level's short side to the screen
+        let aspect_ratio = setup.window_query.get_single().map_or(16. / 9., |window| window.width() / window.height());
+        let level_ratio = level.px_wid as f32 / level.px_hei as f32;
+        if level_ratio > aspect_ratio {
+            // level is wider than the screen
+            Vec2::new(level.px_hei as f32 * aspect_ratio, level.px_hei as f32)
+        } else {
+            // level is taller than the screen
+            Vec2::new(level.px_wid as f32, level.px_wid as f32 / aspect_ratio)
+        }
+    };
+
+    // Camera zones reframe and zoom, eased in by how far the camera has blended into them
+    let zone = overrides.active_zone
+        .zone
+        .as_ref()
+        .map(|(zone, zone_center)| (zone, *zone_center, overrides.active_zone.blend()));
+    let view = match zone {
+        Some((zone, _, blend)) => {
+            // Pixel-perfect mode heads for the canvas `fit_canvas` switches to at the end of the blend,
+            // so the world stays put on screen when it does
+            let zone_view = match setup.canvas.zone_size {
+                Some(size) if setup.settings.pixel_perfect => size.as_vec2(),
+                _ => zone.view(level_view),
+            };
+            level_view.lerp(zone_view, blend)
+        }
+        None => level_view,
+    };
+
+    let delta = time.delta_secs();
+    let player = player_transform.translation.truncate();
+    let target = match overrides.focus.target {
+        // Cutscenes pick the exact spot to look at
+        Some(target) => target,
+        None => {
+            let follow = camera.follow.get_or_insert(player);
+            follow.x = push_deadzone(follow.x, player.x, setup.settings.deadzone.x);
+            // Only re-centre vertically once the player lands, so jumps don't bob the camera
+            follow.y = if ground.on_ground {
+                player.y
+            } else {
+                push_deadzone(follow.y, player.y, setup.settings.deadzone.y)
+            };
+            let follow = *follow;
+
+            let facing = if sprite.flip_x { -1. } else { 1. };
+            let wanted = (facing * setup.settings.look_ahead + player_velocity.linvel.x * setup.settings.look_ahead_per_speed)
+                .clamp(-setup.settings.max_look_ahead, setup.settings.max_look_ahead);
+            let FollowCamera { look_ahead, look_ahead_velocity, .. } = &mut *camera;
+            *look_ahead = smooth_damp(*look_ahead, wanted, look_ahead_velocity, setup.settings.look_ahead_smooth_time, delta);
+
+            let free = follow + Vec2::X * camera.look_ahead;
+            match zone {
+                Some((zone, zone_center, blend)) => free.lerp(zone.target(zone_center, free), blend),
+                None => free,
             }
         }
+    };
+
+    // Jump straight there after a teleport or load instead of sweeping across the world
+    let FollowCamera { center, velocity, .. } = &mut *camera;
+    if center.distance(target) > view.max_element() {
+        *center = target;
+        *velocity = Vec2::ZERO;
+    } else {
+        center.x = smooth_damp(center.x, target.x, &mut velocity.x, setup.settings.smooth_time, delta);
+        center.y = smooth_damp(center.y, target.y, &mut velocity.y, setup.settings.smooth_time, delta);
+    }
+
+    // Shake and kicks go on top, still kept inside the level
+    let shaken = *center + overrides.effects.offset;
+    let level_min = level_transform.translation.truncate();
+    let clamped = Vec2::new(
+        clamp_to_level(shaken.x, view.x, level_min.x, level.px_wid as f32),
+        clamp_to_level(shaken.y, view.y, level_min.y, level.px_hei as f32),
+    );
+
+    // Walking into another level eases over from what was on screen before
+    let (shown, view) = match overrides.level_transition.camera_blend() {
+        Some((from_center, from_view, blend)) => (from_center.lerp(clamped, blend), from_view.lerp(view, blend)),
+        None => (clamped, view),
+    };
+    camera.shown = shown;
+    camera.view = view;
+    orthographic_projection.viewport_origin = Vec2::ZERO;
+    orthographic_projection.scaling_mode = bevy::render::camera::ScalingMode::Fixed {
+        width: view.x,
+        height: view.y,
+    };
+
+    // The viewport origin is the bottom left corner
+    let corner = shown - view / 2.;
+    // Snapping to whole pixels stops sprites shimmering as the camera moves
+    let corner = if setup.settings.pixel_perfect { corner.round() } else { corner };
+    camera_transform.translation.x = corner.x;
+    camera_transform.translation.y = corner.y;
+}
+
+pub struct CameraPlugin;
+
+impl Plugin for CameraPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<CameraFocus>()
+            .init_resource::<CameraSettings>()
+            .add_systems(Startup, spawn_pixel_canvas)
+            .add_systems(Update, (fit_canvas, camera_follow_player).chain());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smooth_damp_settles_without_overshooting() {
+        let mut position = 0.;
+        let mut velocity = 0.;
+        for _ in 0..120 {
+            position = smooth_damp(position, 100., &mut velocity, 0.2, 1. / 60.);
+            assert!(position <= 100.);
+        }
+        assert!((position - 100.).abs() < 0.5, "ended at {}", position);
+    }
+
+    #[test]
+    fn small_levels_are_centred() {
+        assert_eq!(clamp_to_level(500., 400., 0., 300.), 150.);
+        assert_eq!(clamp_to_level(-50., 400., 0., 1000.), 200.);
+        assert_eq!(clamp_to_level(990., 400., 0., 1000.), 800.);
+    }
+
+    #[test]
+    fn canvas_scales_by_whole_numbers() {
+        assert_eq!(canvas_size(Vec2::new(1920., 1080.), 270), (UVec2::new(480, 270), 4));
+        assert_eq!(canvas_size(Vec2::new(1024., 768.), 270), (UVec2::new(512, 384), 2));
+        // Ultrawide gets bars on the sides
+        assert_eq!(canvas_size(Vec2::new(3440., 1440.), 270), (UVec2::new(672, 288), 5));
+        // Tall windows get bars above and below instead of being cropped
+        assert_eq!(canvas_size(Vec2::new(1080., 1920.), 270), (UVec2::new(360, 270), 3));
+        assert_eq!(canvas_size(Vec2::new(300., 1000.), 270), (UVec2::new(300, 225), 1));
+        // Tiny windows still get something
+        assert_eq!(canvas_size(Vec2::new(200., 100.), 270).1, 1);
+    }
+
+    #[test]
+    fn canvas_grows_to_fit_zones() {
+        // The 520x280 boss arena doesn't fit 480x270, so the scale drops from 4 to 3
+        let (size, scale) = canvas_fitting(Vec2::new(1920., 1080.), Vec2::new(520., 292.5));
+        assert_eq!((size, scale), (UVec2::new(640, 360), 3));
+        // Zooming in raises the scale instead
+        assert_eq!(canvas_fitting(Vec2::new(1920., 1080.), Vec2::new(240., 135.)).1, 8);
     }
 }
@@ -0,0 +1,144 @@
+use bevy::prelude::*;
+
+use crate::camera::{camera_follow_player, smooth_damp};
+
+/// Largest shake offset, reached at full trauma
+const MAX_SHAKE_OFFSET: Vec2 = Vec2::new(12., 8.);
+/// Trauma lost per second
+const TRAUMA_DECAY: f32 = 1.5;
+/// How fast the shake wobbles
+const SHAKE_FREQUENCY: f32 = 25.;
+const MAX_KICK: f32 = 24.;
+/// How long a kick takes to settle back
+const KICK_RETURN_SECONDS: f32 = 0.15;
+
+/// Adds trauma, from 0 to 1. Shake grows with the square of trauma,
+/// so small hits barely move the camera and big ones stack up quickly.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct CameraShake {
+    pub trauma: f32,
+}
+
+/// Shoves the camera by `impulse` pixels, then lets it spring back
+#[derive(Event, Debug, Clone, Copy)]
+pub struct CameraKick {
+    pub impulse: Vec2,
+}
+
+/// Slows the whole game to `time_scale` for `seconds` of real time
+#[derive(Event, Debug, Clone, Copy)]
+pub struct HitStop {
+    pub seconds: f32,
+    pub time_scale: f32,
+}
+
+impl HitStop {
+    /// Near freeze for a heavy hit
+    pub fn heavy() -> HitStop {
+        HitStop {
+            seconds: 0.08,
+            time_scale: 0.05,
+        }
+    }
+}
+
+/// Offset added on top of the follow camera's position, before it's clamped to the level
+#[derive(Debug, Default, Resource)]
+pub struct CameraEffects {
+    pub trauma: f32,
+    pub kick: Vec2,
+    /// Shake and kick together, recomputed every frame
+    pub offset: Vec2,
+    kick_velocity: Vec2,
+    /// Virtual time left on the current hit-stop, and the speed it runs at
+    hit_stop: Option<(Timer, f32)>,
+}
+
+impl CameraEffects {
+    fn shake(&self, elapsed: f32) -> Vec2 {
+        // Layered sines stand in for noise, with different frequencies per axis so it doesn't loop visibly
+        let t = elapsed * SHAKE_FREQUENCY;
+        let noise = Vec2::new(
+            (t * 1.0).sin() * 0.6 + (t * 2.3 + 1.7).sin() * 0.4,
+            (t * 1.3 + 4.2).sin() * 0.6 + (t * 2.9 + 0.5).sin() * 0.4,
+        );
+        MAX_SHAKE_OFFSET * noise * self.trauma * self.trauma
+    }
+}
+
+pub fn receive_camera_effects(
+    mut effects: ResMut<CameraEffects>,
+    mut shakes: EventReader<CameraShake>,
+    mut kicks: EventReader<CameraKick>,
+    mut hit_stops: EventReader<HitStop>,
+    mut virtual_time: ResMut<Time<Virtual>>,
+) {
+    for shake in shakes.read() {
+        effects.trauma = (effects.trauma + shake.trauma).clamp(0., 1.);
+    }
+    for kick in kicks.read() {
+        effects.kick = (effects.kick + kick.impulse).clamp_length_max(MAX_KICK);
+    }
+    for hit_stop in hit_stops.read() {
+        // A longer stop already running wins
+        let remaining = effects.hit_stop.as_ref().map_or(0., |(timer, time_scale)| timer.remaining_secs() / time_scale);
+        if hit_stop.seconds > remaining {
+            // Slowed virtual time only covers `time_scale` of every real second
+            let timer = Timer::from_seconds(hit_stop.seconds * hit_stop.time_scale, TimerMode::Once);
+            effects.hit_stop = Some((timer, hit_stop.time_scale));
+            virtual_time.set_relative_speed(hit_stop.time_scale);
+        }
+    }
+}
+
+/// Effects run on real time so a hit-stop doesn't freeze the shake it comes with. The hit-stop
+/// itself is timed on virtual time, which replays step by a fixed amount every frame.
+pub fn update_camera_effects(
+    real_time: Res<Time<Real>>,
+    mut effects: ResMut<CameraEffects>,
+    mut virtual_time: ResMut<Time<Virtual>>,
+) {
+    let delta = real_time.delta_secs();
+    effects.trauma = (effects.trauma - TRAUMA_DECAY * delta).max(0.);
+
+    let CameraEffects { kick, kick_velocity, .. } = &mut *effects;
+    kick.x = smooth_damp(kick.x, 0., &mut kick_velocity.x, KICK_RETURN_SECONDS, delta);
+    kick.y = smooth_damp(kick.y, 0., &mut kick_velocity.y, KICK_RETURN_SECONDS, delta);
+
+    effects.offset = effects.shake(real_time.elapsed_secs()) + effects.kick;
+
+    if let Some((timer, _)) = effects.hit_stop.as_mut() {
+        timer.tick(virtual_time.delta());
+        if timer.finished() {
+            effects.hit_stop = None;
+            virtual_time.set_relative_speed(1.);
+        }
+    }
+}
+
+pub struct CameraEffectsPlugin;
+
+impl Plugin for CameraEffectsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<CameraEffects>()
+            .add_event::<CameraShake>()
+            .add_event::<CameraKick>()
+            .add_event::<HitStop>()
+            .add_systems(Update, (receive_camera_effects, update_camera_effects)
+                .chain()
+                .before(camera_follow_player));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shake_grows_with_trauma_squared() {
+        let shake = |trauma| CameraEffects { trauma, ..Default::default() }.shake(0.3);
+        assert_eq!(shake(0.), Vec2::ZERO);
+        assert!((shake(0.5) * 4. - shake(1.)).length() < 0.001);
+    }
+}
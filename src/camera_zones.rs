@@ -0,0 +1,200 @@
+use bevy::prelude::*;
+use bevy_ecs_ldtk::{
+    prelude::*,
+    utils::{ldtk_pixel_coords_to_translation, ldtk_pixel_coords_to_translation_pivoted},
+};
+
+use crate::{camera::camera_follow_player, player::Player};
+
+/// A rectangle from LDtk that changes how the camera frames the player while they're inside it
+#[derive(Clone, PartialEq, Debug, Component)]
+pub struct CameraZone {
+    pub size: Vec2,
+    /// Frame the whole zone instead of following the player, e.g. for a boss arena
+    pub fixed: bool,
+    /// Multiplies the view size, below 1 zooms in
+    pub zoom: f32,
+    pub lock_x: bool,
+    pub lock_y: bool,
+    /// Looked at instead of the player, relative to the zone's centre
+    pub look_at: Option<Vec2>,
+    /// How long the camera takes to blend in and out
+    pub blend_seconds: f32,
+}
+
+impl Default for CameraZone {
+    fn default() -> Self {
+        CameraZone {
+            size: Vec2::ZERO,
+            fixed: false,
+            zoom: 1.,
+            lock_x: false,
+            lock_y: false,
+            look_at: None,
+            blend_seconds: 0.5,
+        }
+    }
+}
+
+impl CameraZone {
+    /// How much of the world the camera shows while fully inside the zone
+    pub fn view(&self, level_view: Vec2) -> Vec2 {
+        let view = if self.fixed {
+            // Fit the whole zone on screen
+            let aspect_ratio = level_view.x / level_view.y;
+            if self.size.x / self.size.y > aspect_ratio {
+                Vec2::new(self.size.x, self.size.x / aspect_ratio)
+            } else {
+                Vec2::new(self.size.y * aspect_ratio, self.size.y)
+            }
+        } else {
+            level_view
+        };
+        view * self.zoom
+    }
+
+    /// Where the camera looks while fully inside the zone, `follow` being where it would look otherwise
+    pub fn target(&self, center: Vec2, follow: Vec2) -> Vec2 {
+        if let Some(look_at) = self.look_at {
+            return center + look_at;
+        }
+        let mut target = if self.fixed { center } else { follow };
+        if self.lock_x {
+            target.x = center.x;
+        }
+        if self.lock_y {
+            target.y = center.y;
+        }
+        target
+    }
+}
+
+impl LdtkEntity for CameraZone {
+    fn bundle_entity(
+        entity_instance: &EntityInstance,
+        layer_instance: &LayerInstance,
+        _: Option<&Handle<Image>>,
+        _: Option<&TilesetDefinition>,
+        _: &AssetServer,
+        _: &mut Assets<TextureAtlasLayout>,
+    ) -> CameraZone {
+        let level_height = layer_instance.c_hei * layer_instance.grid_size;
+        let size = IVec2::new(entity_instance.width, entity_instance.height);
+        let center = ldtk_pixel_coords_to_translation_pivoted(entity_instance.px, level_height, size, entity_instance.pivot);
+
+        let look_at = entity_instance
+            .get_maybe_point_field("look_at")
+            .ok()
+            .and_then(Option::as_ref)
+            .map(|point| {
+                let pixel_coords = (point.as_vec2() + Vec2::splat(0.5)) * layer_instance.grid_size as f32;
+                ldtk_pixel_coords_to_translation(pixel_coords.as_ivec2(), level_height) - center
+            });
+
+        // Anything missing keeps the default, the entity schema check reports it
+        let defaults = CameraZone::default();
+        CameraZone {
+            size: size.as_vec2(),
+            fixed: entity_instance.get_bool_field("fixed").copied().unwrap_or(defaults.fixed),
+            zoom: entity_instance.get_float_field("zoom").copied().unwrap_or(defaults.zoom),
+            lock_x: entity_instance.get_bool_field("lock_x").copied().unwrap_or(defaults.lock_x),
+            lock_y: entity_instance.get_bool_field("lock_y").copied().unwrap_or(defaults.lock_y),
+            look_at,
+            blend_seconds: entity_instance
+                .get_float_field("blend_seconds")
+                .copied()
+                .unwrap_or(defaults.blend_seconds),
+        }
+    }
+}
+
+#[derive(Default, Bundle, LdtkEntity)]
+pub struct CameraZoneBundle {
+    #[ldtk_entity]
+    pub zone: CameraZone,
+}
+
+/// The zone the camera is blending towards, read by the follow camera
+#[derive(Debug, Default, Resource)]
+pub struct ActiveCameraZone {
+    /// The zone the player is in and its centre, or the one they just left while blending back out
+    pub zone: Option<(CameraZone, Vec2)>,
+    pub inside: bool,
+    /// How far the camera has blended into the zone, from 0 to 1
+    pub weight: f32,
+}
+
+impl ActiveCameraZone {
+    /// The weight eased in and out
+    pub fn blend(&self) -> f32 {
+        let weight = self.weight.clamp(0., 1.);
+        weight * weight * (3. - 2. * weight)
+    }
+}
+
+pub fn track_camera_zone(
+    time: Res<Time>,
+    player_query: Query<&Transform, With<Player>>,
+    zone_query: Query<(&CameraZone, &GlobalTransform)>,
+    mut active: ResMut<ActiveCameraZone>,
+) {
+    let Ok(player_transform) = player_query.get_single() else { return };
+    let player = player_transform.translation.truncate();
+
+    // The smallest zone wins where they overlap
+    let entered = zone_query
+        .iter()
+        .map(|(zone, transform)| (zone, transform.translation().truncate()))
+        .filter(|(zone, center)| Rect::from_center_size(*center, zone.size).contains(player))
+        .min_by(|(a, _), (b, _)| a.size.element_product().total_cmp(&b.size.element_product()));
+
+    active.inside = entered.is_some();
+    if let Some((zone, center)) = entered {
+        active.zone = Some((zone.clone(), center));
+    }
+
+    let Some((zone, _)) = &active.zone else { return };
+    let step = time.delta_secs() / zone.blend_seconds.max(0.0001);
+    if active.inside {
+        active.weight = (active.weight + step).min(1.);
+    } else {
+        active.weight = (active.weight - step).max(0.);
+        if active.weight == 0. {
+            active.zone = None;
+        }
+    }
+}
+
+pub struct CameraZonesPlugin;
+
+impl Plugin for CameraZonesPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<ActiveCameraZone>()
+            .add_systems(Update, track_camera_zone.before(camera_follow_player))
+            .register_ldtk_entity::<CameraZoneBundle>("CameraZone");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zones_override_framing() {
+        let level_view = Vec2::new(640., 360.);
+        let follow = Vec2::new(10., 20.);
+        let center = Vec2::new(100., 100.);
+
+        let arena = CameraZone { size: Vec2::new(520., 280.), fixed: true, ..Default::default() };
+        assert_eq!(arena.target(center, follow), center);
+        assert_eq!(arena.view(level_view).x, 520.);
+
+        let corridor = CameraZone { lock_y: true, zoom: 0.5, ..Default::default() };
+        assert_eq!(corridor.target(center, follow), Vec2::new(10., 100.));
+        assert_eq!(corridor.view(level_view), level_view / 2.);
+
+        let vista = CameraZone { look_at: Some(Vec2::new(0., 50.)), ..Default::default() };
+        assert_eq!(vista.target(center, follow), Vec2::new(100., 150.));
+    }
+}
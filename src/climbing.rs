@@ -2,7 +2,7 @@ use bevy::{prelude::*, utils::HashSet};
 use bevy_ecs_ldtk::prelude::*;
 use bevy_rapier2d::prelude::*;
 
-use crate::colliders::SensorBundle;
+use crate::{app_state::AppState, colliders::SensorBundle};
 
 #[derive(Clone, Eq, PartialEq, Debug, Default, Component)]
 pub struct Climber {
@@ -72,8 +72,7 @@ pub struct ClimbingPlugin;
 
 impl Plugin for ClimbingPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, detect_climb_range)
-            .add_systems(Update, ignore_gravity_if_climbing)
+        app.add_systems(Update, (detect_climb_range, ignore_gravity_if_climbing).run_if(in_state(AppState::Playing)))
             .register_ldtk_int_cell::<LadderBundle>(2);
     }
 }
@@ -3,7 +3,7 @@ use bevy_ecs_ldtk::prelude::*;
 
 use bevy_rapier2d::{prelude::*, rapier::prelude::ColliderBuilder};
 
-use crate::{items::Item, npc::NPC};
+use crate::{doors::Door, items::Item, npc::NPC, shop::Shop};
 
 #[derive(Clone, Default, Bundle, LdtkIntCell)]
 pub struct ColliderBundle {
@@ -18,7 +18,7 @@ pub struct ColliderBundle {
     pub collision_groups: CollisionGroups,
 }
 
-const PLAYER_GROUP: Group = Group::GROUP_1;
+pub const PLAYER_GROUP: Group = Group::GROUP_1;
 const NPC_GROUP: Group = Group::GROUP_2;
 const WORLD_GROUP: Group = Group::GROUP_3;
 const ENEMY_GROUP: Group = Group::GROUP_4;
@@ -154,6 +154,32 @@ pub fn spawn_npc_sensors(mut commands: Commands, query: Query<Entity, Added<NPC>
     }
 }
 
+pub fn spawn_shop_sensors(mut commands: Commands, query: Query<Entity, Added<Shop>>) {
+    for shop_entity in query.iter() {
+        commands.entity(shop_entity).with_children(|parent| {
+            parent.spawn((
+                Collider::cuboid(50., 60.), // Roughly the counter
+                Sensor,
+                ActiveEvents::COLLISION_EVENTS,
+                CollisionGroups::new(PLAYER_GROUP, PLAYER_GROUP),
+            ));
+        });
+    }
+}
+
+pub fn spawn_door_sensors(mut commands: Commands, query: Query<Entity, Added<Door>>) {
+    for door_entity in query.iter() {
+        commands.entity(door_entity).with_children(|parent| {
+            parent.spawn((
+                Collider::cuboid(12., 24.),
+                Sensor,
+                ActiveEvents::COLLISION_EVENTS,
+                CollisionGroups::new(PLAYER_GROUP, PLAYER_GROUP),
+            ));
+        });
+    }
+}
+
 #[derive(Clone, Default, Bundle, LdtkIntCell)]
 pub struct ItemColliderBundle {
     pub collider: Collider,
@@ -168,23 +194,30 @@ pub struct ItemColliderBundle {
 impl From<&EntityInstance> for ItemColliderBundle {
     fn from(entity_instance: &EntityInstance) -> ItemColliderBundle {
         match entity_instance.identifier.as_ref() {
-            "Item" => ItemColliderBundle {
-                collider: Collider::cuboid(8., 8.),
-                // sensor: Sensor,
-                rigid_body: RigidBody::Dynamic,
-                gravity_sale: GravityScale(1.0),
-                active_events: ActiveEvents::COLLISION_EVENTS,
-                rotation_constraints: LockedAxes::ROTATION_LOCKED,
-                collision_groups: CollisionGroups::new(
-                    WORLD_GROUP, // NPC collision group
-                    WORLD_GROUP, // Only detect player collisions
-                ),
-            },
+            "Item" => ItemColliderBundle::item(),
             _ => ItemColliderBundle::default(),
         }
     }
 }
 
+impl ItemColliderBundle {
+    /// Also used for items dropped from the inventory, which don't come from LDtk
+    pub fn item() -> ItemColliderBundle {
+        ItemColliderBundle {
+            collider: Collider::cuboid(8., 8.),
+            // sensor: Sensor,
+            rigid_body: RigidBody::Dynamic,
+            gravity_sale: GravityScale(1.0),
+            active_events: ActiveEvents::COLLISION_EVENTS,
+            rotation_constraints: LockedAxes::ROTATION_LOCKED,
+            collision_groups: CollisionGroups::new(
+                WORLD_GROUP, // NPC collision group
+                WORLD_GROUP, // Only detect player collisions
+            ),
+        }
+    }
+}
+
 #[derive(Clone, Default, Bundle, LdtkIntCell)]
 pub struct GolemColliderBundle {
     pub collider: Collider,
@@ -1,17 +1,300 @@
-use bevy::{prelude::*, text::FontStyle};
+use std::collections::HashMap;
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext},
+    ecs::system::SystemParam,
+    prelude::*,
+};
 use bevy_ecs_ldtk::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    actions::{Action, InputLock},
+    app_state::AppState,
+    boss::StartBossFight,
+    flags::{Condition, GameFlags},
+    interaction::handle_interaction_input,
+    inventory::Inventory,
+    items::{ItemCollected, ItemType},
+    locale::Localizer,
+    player::Player,
+};
+
+/// Most choices a single dialogue node can offer
+const MAX_CHOICES: usize = 4;
+
+/// Guards against nodes without lines that route to each other forever
+const MAX_NODE_HOPS: usize = 32;
+
+const TEXT_FONT_SIZE: f32 = 20.0;
+
+/// The default font is monospaced, every glyph is this many font sizes wide
+const GLYPH_WIDTH: f32 = 0.6;
+
+/// Lines of text the box shows before a line of dialogue continues on the next page
+const LINES_PER_PAGE: usize = 3;
 
+const CHARS_PER_SECOND: f32 = 40.0;
+
+const PORTRAIT_SIZE: f32 = 64.0;
+
+/// Points an entity at a dialogue graph in `assets/dialogue/<id>.dialogue.ron`.
+/// The id comes from the LDtk `dialogue` field.
 #[derive(Debug, Default, Component, Reflect)]
 pub struct Dialogue {
+    pub id: String,
+}
+
+#[derive(Debug, Component)]
+pub struct DialogueHandle(pub Handle<DialogueGraph>);
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DialogueLine {
+    /// Overrides the node's speaker for this line
+    #[serde(default)]
+    pub speaker: Option<String>,
+    /// Key into the string tables in `assets/locale`
     pub text: String,
 }
 
-#[derive(Component)]
-pub struct DialogueUI {
-    pub active: bool,
+/// Changes a conversation makes to the world, applied when a node is entered or a choice is picked
+#[derive(Event, Debug, Clone, PartialEq, Deserialize)]
+pub enum DialogueEffect {
+    SetFlag(String, bool),
+    SetInt(String, i32),
+    AddInt(String, i32),
+    SetString(String, String),
+    GiveItem(ItemType, u32),
+    StartBossFight,
+    /// Handled by the quest log, see `quests.rs`
+    StartQuest(String),
+    CompleteQuest(String),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DialogueChoice {
+    /// Key into the string tables in `assets/locale`
     pub text: String,
+    /// Node to jump to, or end the conversation if empty
+    #[serde(default)]
+    pub next: Option<String>,
+    /// The choice is only offered if all of these hold
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    #[serde(default)]
+    pub effects: Vec<DialogueEffect>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DialogueBranch {
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    pub next: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DialogueNode {
+    #[serde(default)]
+    pub speaker: Option<String>,
+    /// A node without lines only routes to the next one
+    #[serde(default)]
+    pub lines: Vec<DialogueLine>,
+    /// Offered after the last line, instead of following `branches` or `next`
+    #[serde(default)]
+    pub choices: Vec<DialogueChoice>,
+    /// The first branch whose conditions hold decides where to go, `next` is the fallback
+    #[serde(default)]
+    pub branches: Vec<DialogueBranch>,
+    #[serde(default)]
+    pub next: Option<String>,
+    #[serde(default)]
+    pub effects: Vec<DialogueEffect>,
+}
+
+impl DialogueNode {
+    pub fn visible_choices(&self, flags: &GameFlags) -> Vec<&DialogueChoice> {
+        self.choices
+            .iter()
+            .filter(|choice| Condition::all_hold(&choice.conditions, flags))
+            .take(MAX_CHOICES)
+            .collect()
+    }
+
+    /// Where the conversation goes once this node is done and it has no choices
+    pub fn follow(&self, flags: &GameFlags) -> Option<String> {
+        self.branches
+            .iter()
+            .find(|branch| Condition::all_hold(&branch.conditions, flags))
+            .map(|branch| branch.next.clone())
+            .unwrap_or_else(|| self.next.clone())
+    }
+}
+
+#[derive(Debug, Asset, TypePath, Deserialize)]
+pub struct DialogueGraph {
+    pub start: String,
+    pub nodes: HashMap<String, DialogueNode>,
+}
+
+#[derive(Default)]
+pub struct DialogueLoader;
+
+impl AssetLoader for DialogueLoader {
+    type Asset = DialogueGraph;
+    type Settings = ();
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<DialogueGraph, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        Ok(ron::de::from_bytes(&bytes)?)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["dialogue.ron"]
+    }
+}
+
+#[derive(Event, Debug, Clone)]
+pub struct StartDialogue {
+    pub graph: Handle<DialogueGraph>,
+    /// Who speaks when a node doesn't name a speaker
+    pub speaker: String,
+}
+
+#[derive(Event, Debug, Clone)]
+pub struct DialogueEnded;
+
+#[derive(Debug, Clone)]
+pub struct ActiveDialogue {
+    pub graph: Handle<DialogueGraph>,
+    pub speaker: String,
+    pub node: String,
+    pub line: usize,
+    pub selected: usize,
+    /// The current line split into pages that fit the box, empty until laid out
+    pub pages: Vec<String>,
+    pub page: usize,
+    /// Characters of the current page shown so far
+    pub revealed: f32,
+}
+
+impl ActiveDialogue {
+    pub fn show_line(&mut self, line: usize) {
+        self.line = line;
+        self.pages.clear();
+        self.page = 0;
+        self.revealed = 0.0;
+    }
+
+    pub fn page_text(&self) -> &str {
+        self.pages.get(self.page).map(String::as_str).unwrap_or_default()
+    }
+
+    pub fn page_revealed(&self) -> bool {
+        !self.pages.is_empty() && self.revealed as usize >= self.page_text().chars().count()
+    }
+
+    pub fn reveal_page(&mut self) {
+        self.revealed = self.page_text().chars().count() as f32;
+    }
+
+    pub fn on_last_page(&self) -> bool {
+        self.page + 1 >= self.pages.len()
+    }
+
+    /// Every line of the node has been shown in full, so its choices can be offered
+    pub fn node_finished(&self, node: &DialogueNode) -> bool {
+        self.line + 1 >= node.lines.len() && self.on_last_page() && self.page_revealed()
+    }
+
+    pub fn line_speaker<'a>(&'a self, node: &'a DialogueNode) -> &'a str {
+        node.lines
+            .get(self.line)
+            .and_then(|line| line.speaker.as_ref())
+            .or(node.speaker.as_ref())
+            .unwrap_or(&self.speaker)
+    }
 }
 
+/// Word wraps `text` to `chars_per_line` and groups the wrapped lines into pages
+pub fn paginate(text: &str, chars_per_line: usize, lines_per_page: usize) -> Vec<String> {
+    let chars_per_line = chars_per_line.max(1);
+    let mut lines: Vec<String> = Vec::new();
+    let mut current = String::new();
+
+    for word in text.split_whitespace() {
+        let mut word: Vec<char> = word.chars().collect();
+        // Words too long for a whole line get cut up
+        while word.len() > chars_per_line {
+            if !current.is_empty() {
+                lines.push(std::mem::take(&mut current));
+            }
+            lines.push(word.drain(..chars_per_line).collect());
+        }
+        if word.is_empty() {
+            continue;
+        }
+
+        let length = current.chars().count();
+        if length > 0 && length + 1 + word.len() > chars_per_line {
+            lines.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.extend(word);
+    }
+    if !current.is_empty() || lines.is_empty() {
+        lines.push(current);
+    }
+
+    lines
+        .chunks(lines_per_page.max(1))
+        .map(|page| page.join("\n"))
+        .collect()
+}
+
+/// The conversation currently on screen, if any
+#[derive(Debug, Default, Resource)]
+pub struct DialogueRunner {
+    pub active: Option<ActiveDialogue>,
+}
+
+impl DialogueRunner {
+    pub fn is_active(&self) -> bool {
+        self.active.is_some()
+    }
+}
+
+#[derive(Component)]
+pub struct DialogueUI;
+
+#[derive(Component)]
+pub struct DialogueText;
+
+/// The column holding the text, its width decides how lines are paginated
+#[derive(Component)]
+pub struct DialogueTextBox;
+
+#[derive(Component)]
+pub struct DialogueSpeaker;
+
+#[derive(Component)]
+pub struct DialoguePortrait;
+
+/// Shown once a page is fully revealed and Interact moves on
+#[derive(Component)]
+pub struct DialogueContinue;
+
+#[derive(Component)]
+pub struct DialogueChoiceRow(pub usize);
+
 // System to spawn dialogue UI
 pub fn spawn_dialogue_ui(mut commands: Commands) {
     // Spawn hidden dialogue box
@@ -23,53 +306,517 @@ pub fn spawn_dialogue_ui(mut commands: Commands) {
                 left: Val::Px(20.0),
                 right: Val::Px(20.0),
                 padding: UiRect::all(Val::Px(10.0)),
+                column_gap: Val::Px(10.0),
                 ..Default::default()
             },
             Visibility::Hidden,
             BackgroundColor::from(Color::srgba(0.2, 0.2, 0.2, 0.8)),
-            DialogueUI {
-                active: false,
-                text: String::new(),
-            },
+            DialogueUI,
         ))
         .with_children(|parent| {
             parent.spawn((
-                Text::new(""),
+                ImageNode::default(),
+                Node {
+                    width: Val::Px(PORTRAIT_SIZE),
+                    height: Val::Px(PORTRAIT_SIZE),
+                    flex_shrink: 0.0,
+                    ..Default::default()
+                },
+                Visibility::Hidden,
+                DialoguePortrait,
+            ));
+            parent
+                .spawn((
+                    Node {
+                        flex_grow: 1.0,
+                        flex_direction: FlexDirection::Column,
+                        ..Default::default()
+                    },
+                    DialogueTextBox,
+                ))
+                .with_children(|parent| {
+                    parent.spawn((
+                        Text::new(""),
+                        TextFont {
+                            font_size: 16.0,
+                            ..Default::default()
+                        },
+                        TextColor(Color::srgb(1.0, 0.85, 0.4)),
+                        DialogueSpeaker,
+                    ));
+                    parent.spawn((
+                        Text::new(""),
+                        TextFont {
+                            font_size: TEXT_FONT_SIZE,
+                            ..Default::default()
+                        },
+                        TextColor(Color::WHITE),
+                        // Fixed height so the box doesn't jump around while text types out
+                        Node {
+                            min_height: Val::Px(LINES_PER_PAGE as f32 * TEXT_FONT_SIZE * 1.2),
+                            ..Default::default()
+                        },
+                        DialogueText,
+                    ));
+                    for index in 0..MAX_CHOICES {
+                        parent.spawn((
+                            Text::new(""),
+                            TextFont {
+                                font_size: 18.0,
+                                ..Default::default()
+                            },
+                            TextColor(Color::WHITE),
+                            Visibility::Hidden,
+                            DialogueChoiceRow(index),
+                        ));
+                    }
+                });
+            parent.spawn((
+                Text::new(">>"),
                 TextFont {
-                    font_size: 20.0,
+                    font_size: 16.0,
                     ..Default::default()
                 },
                 TextColor(Color::WHITE),
+                Node {
+                    position_type: PositionType::Absolute,
+                    right: Val::Px(10.0),
+                    bottom: Val::Px(6.0),
+                    ..Default::default()
+                },
+                Visibility::Hidden,
+                DialogueContinue,
             ));
         });
 }
 
-// System to update dialogue UI
-pub fn update_dialogue_ui(
-    mut dialogue_query: Query<(&mut Visibility, &DialogueUI, &Children)>,
-    mut text_query: Query<&mut Text>,
+/// Kicks off loading the graph for every entity with a [Dialogue]
+pub fn load_dialogue_graphs(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    query: Query<(Entity, &Dialogue), Added<Dialogue>>,
 ) {
-    for (mut visibility, dialogue, children) in dialogue_query.iter_mut() {
-        if dialogue.active {
-            *visibility = Visibility::Visible;
-            
-            // Update text
-            if let Ok(mut text) = text_query.get_mut(children[0]) {
-                text.0 = dialogue.text.clone();
+    for (entity, dialogue) in query.iter() {
+        let graph = asset_server.load(format!("dialogue/{}.dialogue.ron", dialogue.id));
+        commands.entity(entity).insert(DialogueHandle(graph));
+    }
+}
+
+/// Moves to `next`, applying each entered node's effects and routing through
+/// nodes without lines. Returns false when the conversation is over.
+fn enter_node(
+    active: &mut ActiveDialogue,
+    graph: &DialogueGraph,
+    flags: &GameFlags,
+    effects: &mut EventWriter<DialogueEffect>,
+    mut next: Option<String>,
+) -> bool {
+    for _ in 0..MAX_NODE_HOPS {
+        let Some(id) = next else { return false };
+        let Some(node) = graph.nodes.get(&id) else {
+            warn!("Dialogue node {} doesn't exist", id);
+            return false;
+        };
+
+        effects.send_batch(node.effects.iter().cloned());
+
+        if !node.lines.is_empty() {
+            active.node = id;
+            active.selected = 0;
+            active.show_line(0);
+            return true;
+        }
+        next = node.follow(flags);
+    }
+
+    warn!("Dialogue for {} routed through too many nodes", active.speaker);
+    false
+}
+
+pub fn start_dialogue(
+    mut start_events: EventReader<StartDialogue>,
+    mut runner: ResMut<DialogueRunner>,
+    graphs: Res<Assets<DialogueGraph>>,
+    flags: Res<GameFlags>,
+    mut effects: EventWriter<DialogueEffect>,
+) {
+    for event in start_events.read() {
+        let Some(graph) = graphs.get(&event.graph) else {
+            warn!("Dialogue for {} hasn't loaded yet", event.speaker);
+            continue;
+        };
+
+        let mut active = ActiveDialogue {
+            graph: event.graph.clone(),
+            speaker: event.speaker.clone(),
+            node: graph.start.clone(),
+            line: 0,
+            selected: 0,
+            pages: Vec::new(),
+            page: 0,
+            revealed: 0.0,
+        };
+        if enter_node(&mut active, graph, &flags, &mut effects, Some(graph.start.clone())) {
+            runner.active = Some(active);
+        }
+    }
+}
+
+pub fn end_dialogue(runner: &mut DialogueRunner, ended: &mut EventWriter<DialogueEnded>) {
+    if runner.active.take().is_some() {
+        ended.send(DialogueEnded);
+    }
+}
+
+pub fn advance_dialogue(
+    actions: Res<ButtonInput<Action>>,
+    mut runner: ResMut<DialogueRunner>,
+    graphs: Res<Assets<DialogueGraph>>,
+    flags: Res<GameFlags>,
+    mut ended: EventWriter<DialogueEnded>,
+    mut effects: EventWriter<DialogueEffect>,
+    lock: Res<InputLock>,
+) {
+    // The inventory, map and bindings menus can open mid-conversation and use the same keys
+    if lock.menu.is_some() {
+        return;
+    }
+    let Some(active) = runner.active.as_mut() else { return };
+    let Some(graph) = graphs.get(&active.graph) else { return };
+    let Some(node) = graph.nodes.get(&active.node) else {
+        warn!("Dialogue node {} doesn't exist", active.node);
+        end_dialogue(&mut runner, &mut ended);
+        return;
+    };
+
+    // Pressing while text is still typing out shows the whole page at once
+    if !active.page_revealed() {
+        if actions.just_pressed(Action::Interact) && !active.pages.is_empty() {
+            active.reveal_page();
+        }
+        return;
+    }
+
+    let on_last_line = active.node_finished(node);
+    let choices = node.visible_choices(&flags);
+    let choosing = on_last_line && !choices.is_empty();
+
+    if choosing {
+        let count = choices.len();
+        if actions.just_pressed(Action::MenuUp) {
+            active.selected = (active.selected + count - 1) % count;
+        }
+        if actions.just_pressed(Action::MenuDown) {
+            active.selected = (active.selected + 1) % count;
+        }
+    }
+
+    let confirmed = actions.just_pressed(Action::Interact)
+        || (choosing && actions.just_pressed(Action::MenuConfirm));
+    if !confirmed {
+        return;
+    }
+
+    if !active.on_last_page() {
+        active.page += 1;
+        active.revealed = 0.0;
+        return;
+    }
+
+    if !on_last_line {
+        active.show_line(active.line + 1);
+        return;
+    }
+
+    let next = match choices.get(active.selected) {
+        Some(choice) if choosing => {
+            effects.send_batch(choice.effects.iter().cloned());
+            choice.next.clone()
+        }
+        _ => node.follow(&flags),
+    };
+
+    if !enter_node(active, graph, &flags, &mut effects, next) {
+        end_dialogue(&mut runner, &mut ended);
+    }
+}
+
+pub fn apply_dialogue_effects(
+    mut effects: EventReader<DialogueEffect>,
+    mut flags: ResMut<GameFlags>,
+    mut player_query: Query<&mut Inventory, With<Player>>,
+    mut boss_fight: EventWriter<StartBossFight>,
+    mut collected: EventWriter<ItemCollected>,
+) {
+    for effect in effects.read() {
+        match effect {
+            DialogueEffect::SetFlag(key, value) => flags.set_bool(key.clone(), *value),
+            DialogueEffect::SetInt(key, value) => flags.set_int(key.clone(), *value),
+            DialogueEffect::AddInt(key, amount) => flags.add_int(key.clone(), *amount),
+            DialogueEffect::SetString(key, value) => flags.set_string(key.clone(), value.clone()),
+            DialogueEffect::GiveItem(item_type, count) => {
+                if let Ok(mut inventory) = player_query.get_single_mut() {
+                    inventory.add(*item_type, *count);
+                    collected.send(ItemCollected {
+                        item_type: *item_type,
+                        count: *count,
+                    });
+                }
+            }
+            DialogueEffect::StartBossFight => {
+                boss_fight.send(StartBossFight);
             }
+            DialogueEffect::StartQuest(_) | DialogueEffect::CompleteQuest(_) => (),
+        }
+    }
+}
+
+pub fn lock_input_during_dialogue(runner: Res<DialogueRunner>, mut lock: ResMut<InputLock>) {
+    if runner.is_changed() {
+        lock.dialogue = runner.is_active();
+    }
+}
+
+/// Splits the current line into pages once the dialogue box has been laid out
+pub fn paginate_dialogue(
+    mut runner: ResMut<DialogueRunner>,
+    graphs: Res<Assets<DialogueGraph>>,
+    localizer: Localizer,
+    box_query: Query<&ComputedNode, With<DialogueTextBox>>,
+) {
+    let Some(active) = runner.active.as_mut() else { return };
+    // A translation needs different page breaks, the line starts over
+    if localizer.changed() {
+        active.show_line(active.line);
+    }
+    if !active.pages.is_empty() {
+        return;
+    }
+    let Some(line) = graphs
+        .get(&active.graph)
+        .and_then(|graph| graph.nodes.get(&active.node))
+        .and_then(|node| node.lines.get(active.line))
+    else {
+        return;
+    };
+
+    let width = box_query
+        .get_single()
+        .map(|computed| computed.size().x * computed.inverse_scale_factor())
+        .unwrap_or_default();
+    // The box has no size before its first layout
+    if width <= 0.0 {
+        return;
+    }
+
+    let chars_per_line = (width / (TEXT_FONT_SIZE * GLYPH_WIDTH)) as usize;
+    active.pages = paginate(&localizer.get(&line.text), chars_per_line, LINES_PER_PAGE);
+}
+
+pub fn type_dialogue_text(time: Res<Time>, mut runner: ResMut<DialogueRunner>) {
+    let Some(active) = runner.active.as_mut() else { return };
+    if active.pages.is_empty() || active.page_revealed() {
+        return;
+    }
+    active.revealed += CHARS_PER_SECOND * time.delta_secs();
+    if active.page_revealed() {
+        active.reveal_page();
+    }
+}
+
+/// The parts of the dialogue box that change while talking
+#[allow(clippy::type_complexity)]
+#[derive(SystemParam)]
+pub struct DialogueWidgets<'w, 's> {
+    dialogue_query: Query<'w, 's, &'static mut Visibility, With<DialogueUI>>,
+    text_query: Query<'w, 's, &'static mut Text, (With<DialogueText>, Without<DialogueSpeaker>, Without<DialogueChoiceRow>)>,
+    speaker_query: Query<'w, 's, &'static mut Text, (With<DialogueSpeaker>, Without<DialogueChoiceRow>)>,
+    choice_query: Query<'w, 's, (&'static mut Text, &'static mut Visibility, &'static DialogueChoiceRow), Without<DialogueUI>>,
+    continue_query: Query<'w, 's, &'static mut Visibility, (With<DialogueContinue>, Without<DialogueUI>, Without<DialogueChoiceRow>)>,
+}
+
+/// `Text` has no `PartialEq`, so this stands in for `set_if_neq`
+fn set_text(text: &mut Mut<Text>, value: String) {
+    if text.0 != value {
+        text.0 = value;
+    }
+}
+
+// System to update dialogue UI. Only writes what differs, so the text isn't laid out again every frame.
+pub fn update_dialogue_ui(
+    time: Res<Time>,
+    runner: Res<DialogueRunner>,
+    graphs: Res<Assets<DialogueGraph>>,
+    flags: Res<GameFlags>,
+    localizer: Localizer,
+    mut widgets: DialogueWidgets,
+) {
+    let current = runner.active.as_ref().and_then(|active| {
+        let node = graphs.get(&active.graph)?.nodes.get(&active.node)?;
+        Some((active, node))
+    });
+
+    for mut visibility in widgets.dialogue_query.iter_mut() {
+        visibility.set_if_neq(if current.is_some() {
+            Visibility::Visible
         } else {
-            *visibility = Visibility::Hidden;
+            Visibility::Hidden
+        });
+    }
+
+    let Some((active, node)) = current else { return };
+
+    if let Ok(mut text) = widgets.speaker_query.get_single_mut() {
+        let speaker = active.line_speaker(node);
+        set_text(&mut text, localizer.get_or(&format!("name.{}", speaker), speaker));
+    }
+
+    if let Ok(mut text) = widgets.text_query.get_single_mut() {
+        set_text(&mut text, active.page_text().chars().take(active.revealed as usize).collect());
+    }
+
+    let choosing = active.node_finished(node);
+    let choices = node.visible_choices(&flags);
+    for (mut text, mut visibility, row) in widgets.choice_query.iter_mut() {
+        match choices.get(row.0).filter(|_| choosing) {
+            Some(choice) => {
+                let marker = if row.0 == active.selected { ">" } else { " " };
+                set_text(&mut text, format!("{} {}", marker, localizer.get(&choice.text)));
+                visibility.set_if_neq(Visibility::Inherited);
+            }
+            None => {
+                visibility.set_if_neq(Visibility::Hidden);
+            }
         }
     }
+
+    // Blinks while waiting for Interact, choices are their own prompt
+    let waiting = active.page_revealed() && (!choosing || choices.is_empty());
+    let blink_on = time.elapsed_secs().fract() < 0.6;
+    for mut visibility in widgets.continue_query.iter_mut() {
+        visibility.set_if_neq(if waiting && blink_on {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        });
+    }
+}
+
+/// Shows the first frame of the speaking character's sprite sheet next to the text
+pub fn update_dialogue_portrait(
+    runner: Res<DialogueRunner>,
+    graphs: Res<Assets<DialogueGraph>>,
+    speaker_query: Query<(&Name, &Sprite)>,
+    mut portrait_query: Query<(&mut ImageNode, &mut Visibility), With<DialoguePortrait>>,
+) {
+    let Ok((mut portrait, mut visibility)) = portrait_query.get_single_mut() else { return };
+
+    let sprite = runner
+        .active
+        .as_ref()
+        .and_then(|active| {
+            let node = graphs.get(&active.graph)?.nodes.get(&active.node)?;
+            Some(active.line_speaker(node))
+        })
+        .and_then(|speaker| {
+            speaker_query
+                .iter()
+                .find(|(name, _)| name.as_str() == speaker)
+                .map(|(_, sprite)| sprite)
+        });
+
+    let Some(sprite) = sprite else {
+        visibility.set_if_neq(Visibility::Hidden);
+        return;
+    };
+
+    // Only touch the image when the speaker changes so the UI isn't re-laid out every frame
+    if portrait.image != sprite.image {
+        portrait.image = sprite.image.clone();
+        portrait.texture_atlas = sprite.texture_atlas.clone().map(|atlas| TextureAtlas { index: 0, ..atlas });
+    }
+    visibility.set_if_neq(Visibility::Inherited);
 }
 
 impl Dialogue {
     pub fn from_field(entity_instance: &EntityInstance) -> Dialogue {
         Dialogue {
-            text: entity_instance
+            id: entity_instance
                 .get_string_field("dialogue")
-                .expect("expected entity to have non-nullable dialogue String field")
-                .clone()
+                .cloned()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+pub struct DialoguePlugin;
+
+impl Plugin for DialoguePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .register_type::<Dialogue>()
+            .init_asset::<DialogueGraph>()
+            .init_asset_loader::<DialogueLoader>()
+            .init_resource::<DialogueRunner>()
+            .add_event::<StartDialogue>()
+            .add_event::<DialogueEnded>()
+            .add_event::<DialogueEffect>()
+            .add_systems(Startup, spawn_dialogue_ui)
+            .add_systems(Update, (
+                load_dialogue_graphs,
+                // Runs before start_dialogue so the Interact press that opens a
+                // conversation doesn't also skip its first line
+                advance_dialogue.after(handle_interaction_input),
+                start_dialogue,
+                apply_dialogue_effects,
+                lock_input_during_dialogue,
+                paginate_dialogue,
+                type_dialogue_text,
+                update_dialogue_ui,
+                update_dialogue_portrait,
+            ).chain().run_if(in_state(AppState::Playing)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_lines_wrap_onto_pages() {
+        let pages = paginate("The golem is just east of here, past the old fence", 12, 2);
+        assert_eq!(pages, vec![
+            "The golem is\njust east of",
+            "here, past\nthe old",
+            "fence",
+        ]);
+
+        assert_eq!(paginate("Raaaaaaaaargh!", 5, 3), vec!["Raaaa\naaaaa\nrgh!"]);
+        assert_eq!(paginate("", 10, 3), vec![""]);
+    }
+
+    #[test]
+    fn bundled_dialogue_graphs_are_connected() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/dialogue");
+        for entry in std::fs::read_dir(dir).unwrap().flatten() {
+            let contents = std::fs::read_to_string(entry.path()).unwrap();
+            let graph: DialogueGraph = ron::from_str(&contents)
+                .unwrap_or_else(|err| panic!("{:?} should parse: {}", entry.path(), err));
+
+            assert!(graph.nodes.contains_key(&graph.start), "{:?} start node is missing", entry.path());
+            for (id, node) in &graph.nodes {
+                let routes = !node.branches.is_empty() || node.next.is_some();
+                assert!(!node.lines.is_empty() || routes, "{:?} node {} is a dead end", entry.path(), id);
+                let targets = node
+                    .choices
+                    .iter()
+                    .filter_map(|c| c.next.as_ref())
+                    .chain(node.branches.iter().filter_map(|b| b.next.as_ref()))
+                    .chain(node.next.as_ref());
+                for target in targets {
+                    assert!(graph.nodes.contains_key(target), "{:?} node {} jumps to missing {}", entry.path(), id, target);
+                }
+            }
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,203 @@
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::{actions::InputLock, app_state::AppState, colliders::spawn_door_sensors, game_flow::LdtkProjects, player::Player};
+
+const FADE_SECONDS: f32 = 0.35;
+
+/// Half the player collider's height, so they arrive standing at the door
+const PLAYER_HALF_HEIGHT: f32 = 28.;
+
+/// Leads to the door in its LDtk `destination` field, which can be in any level
+#[derive(Debug, Clone, Default, Component)]
+pub struct Door {
+    pub destination: Option<ldtk::EntityRef>,
+}
+
+impl Door {
+    pub fn from_field(entity_instance: &EntityInstance) -> Door {
+        Door {
+            destination: entity_instance
+                .get_maybe_entity_ref_field("destination")
+                .ok()
+                .cloned()
+                .flatten(),
+        }
+    }
+}
+
+fn door_sprite(entity_instance: &EntityInstance) -> Sprite {
+    Sprite::from_color(
+        Color::srgb(0.35, 0.23, 0.13),
+        Vec2::new(entity_instance.width as f32, entity_instance.height as f32),
+    )
+}
+
+#[derive(Default, Bundle, LdtkEntity)]
+pub struct DoorBundle {
+    #[with(Door::from_field)]
+    door: Door,
+    #[with(door_sprite)]
+    sprite: Sprite,
+}
+
+/// Sent from the interaction prompt
+#[derive(Event, Debug, Clone)]
+pub struct UseDoor {
+    pub door: Entity,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TransitionPhase {
+    #[default]
+    Idle,
+    FadingOut,
+    /// Waiting for the destination level to spawn
+    Loading,
+    FadingIn,
+}
+
+#[derive(Debug, Default, Resource)]
+pub struct DoorTransition {
+    pub phase: TransitionPhase,
+    pub timer: Timer,
+    /// Where the player's centre ends up, in world coordinates
+    pub target: Vec2,
+    pub level: String,
+}
+
+#[derive(Component)]
+pub struct FadeOverlay;
+
+/// World position of the bottom centre of `destination` in `level`, which is where the player stands on arrival.
+/// Works from the project data, so `level` doesn't have to be spawned.
+pub fn destination_position(level: &Level, destination: &ldtk::EntityRef) -> Option<Vec2> {
+    let entity = level
+        .layer_instances
+        .iter()
+        .flatten()
+        .flat_map(|layer| &layer.entity_instances)
+        .find(|entity| entity.iid == destination.entity_iid)?;
+
+    // LDtk's y axis points down
+    let x = level.world_x as f32 + entity.px.x as f32 + (0.5 - entity.pivot.x) * entity.width as f32;
+    let y = level.world_y as f32 + entity.px.y as f32 + (1. - entity.pivot.y) * entity.height as f32;
+    Some(Vec2::new(x, -y))
+}
+
+pub fn spawn_fade_overlay(mut commands: Commands) {
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            width: Val::Percent(100.),
+            height: Val::Percent(100.),
+            ..Default::default()
+        },
+        BackgroundColor(Color::NONE),
+        GlobalZIndex(10),
+        FadeOverlay,
+    ));
+}
+
+pub fn start_door_transition(
+    mut events: EventReader<UseDoor>,
+    door_query: Query<&Door>,
+    ldtk_projects: LdtkProjects,
+    mut transition: ResMut<DoorTransition>,
+    mut lock: ResMut<InputLock>,
+) {
+    for event in events.read() {
+        if transition.phase != TransitionPhase::Idle {
+            continue;
+        }
+        let Some(destination) = door_query.get(event.door).ok().and_then(|door| door.destination.as_ref()) else {
+            warn!("Door {:?} doesn't lead anywhere", event.door);
+            continue;
+        };
+        let Some(position) = ldtk_projects
+            .level_by_iid(&destination.level_iid)
+            .and_then(|level| destination_position(level, destination))
+        else {
+            warn!("Door destination {} isn't in any loaded LDtk project", destination.entity_iid);
+            continue;
+        };
+
+        *transition = DoorTransition {
+            phase: TransitionPhase::FadingOut,
+            timer: Timer::from_seconds(FADE_SECONDS, TimerMode::Once),
+            target: position + Vec2::Y * PLAYER_HALF_HEIGHT,
+            level: destination.level_iid.clone(),
+        };
+        lock.cutscene = true;
+    }
+}
+
+pub fn run_door_transition(
+    time: Res<Time>,
+    mut transition: ResMut<DoorTransition>,
+    mut lock: ResMut<InputLock>,
+    mut level_selection: ResMut<LevelSelection>,
+    level_query: Query<&LevelIid>,
+    mut player_query: Query<(&mut Transform, &mut Velocity), With<Player>>,
+) {
+    if transition.phase == TransitionPhase::Idle {
+        return;
+    }
+    transition.timer.tick(time.delta());
+
+    if transition.phase == TransitionPhase::FadingOut {
+        if !transition.timer.finished() {
+            return;
+        }
+        *level_selection = LevelSelection::iid(transition.level.clone());
+        transition.phase = TransitionPhase::Loading;
+    }
+
+    // Hold the player at the door until the level's colliders are there to stand on
+    if let Ok((mut transform, mut velocity)) = player_query.get_single_mut() {
+        transform.translation.x = transition.target.x;
+        transform.translation.y = transition.target.y;
+        *velocity = Velocity::zero();
+    }
+
+    match transition.phase {
+        TransitionPhase::Loading if level_query.iter().any(|iid| iid.as_str() == transition.level) => {
+            transition.phase = TransitionPhase::FadingIn;
+            transition.timer = Timer::from_seconds(FADE_SECONDS, TimerMode::Once);
+        }
+        TransitionPhase::FadingIn if transition.timer.finished() => {
+            transition.phase = TransitionPhase::Idle;
+            lock.cutscene = false;
+        }
+        _ => (),
+    }
+}
+
+pub fn update_fade_overlay(transition: Res<DoorTransition>, mut overlay_query: Query<&mut BackgroundColor, With<FadeOverlay>>) {
+    let alpha = match transition.phase {
+        TransitionPhase::Idle => 0.,
+        TransitionPhase::FadingOut => transition.timer.fraction(),
+        TransitionPhase::Loading => 1.,
+        TransitionPhase::FadingIn => 1. - transition.timer.fraction(),
+    };
+    for mut color in overlay_query.iter_mut() {
+        color.0 = Color::srgba(0., 0., 0., alpha);
+    }
+}
+
+pub struct DoorsPlugin;
+
+impl Plugin for DoorsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_event::<UseDoor>()
+            .init_resource::<DoorTransition>()
+            .add_systems(Startup, spawn_fade_overlay)
+            .add_systems(Update, (
+                spawn_door_sensors,
+                (start_door_transition, run_door_transition, update_fade_overlay).chain(),
+            ).run_if(in_state(AppState::Playing)))
+            .register_ldtk_entity::<DoorBundle>("Door");
+    }
+}
@@ -0,0 +1,282 @@
+use std::{fmt, str::FromStr};
+
+use bevy::prelude::*;
+use bevy_ecs_ldtk::{ldtk::FieldValue, prelude::*};
+
+use crate::items::ItemType;
+
+/// The kinds of LDtk field the entity bundles read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldKind {
+    Int,
+    Float,
+    Bool,
+    String,
+    Point,
+    EntityRef,
+    /// An `Item` enum
+    Item,
+    Ints,
+    Points,
+    Items,
+}
+
+impl FieldKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FieldKind::Int => "Int",
+            FieldKind::Float => "Float",
+            FieldKind::Bool => "Bool",
+            FieldKind::String => "String",
+            FieldKind::Point => "Point",
+            FieldKind::EntityRef => "Entity ref",
+            FieldKind::Item => "Item enum",
+            FieldKind::Ints => "Array<Int>",
+            FieldKind::Points => "Array<Point>",
+            FieldKind::Items => "Array<Item enum>",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldSpec {
+    pub identifier: &'static str,
+    pub kind: FieldKind,
+    /// Whether the value, or elements of an array, may be left empty in the editor
+    pub nullable: bool,
+}
+
+const fn required(identifier: &'static str, kind: FieldKind) -> FieldSpec {
+    FieldSpec { identifier, kind, nullable: false }
+}
+
+const fn nullable(identifier: &'static str, kind: FieldKind) -> FieldSpec {
+    FieldSpec { identifier, kind, nullable: true }
+}
+
+/// Fields each registered LDtk entity reads when it spawns.
+/// Keep in sync with the `#[with(...)]` and `LdtkEntity` impls of the bundles.
+pub const ENTITY_SCHEMAS: &[(&str, &[FieldSpec])] = &[
+    ("Player", &[
+        required("name", FieldKind::String),
+        required("inventory", FieldKind::Items),
+        required("health", FieldKind::Int),
+    ]),
+    ("NPC", &[
+        required("name", FieldKind::String),
+        required("dialogue", FieldKind::String),
+    ]),
+    ("Golem", &[
+        required("loot", FieldKind::Items),
+        required("health", FieldKind::Int),
+        required("dialogue", FieldKind::String),
+        required("patrol", FieldKind::Points),
+    ]),
+    ("Item", &[
+        required("type", FieldKind::Item),
+        required("count", FieldKind::Int),
+    ]),
+    ("Shop", &[
+        required("stock", FieldKind::Items),
+        required("prices", FieldKind::Ints),
+    ]),
+    ("Door", &[
+        nullable("destination", FieldKind::EntityRef),
+    ]),
+    ("CameraZone", &[
+        required("fixed", FieldKind::Bool),
+        required("zoom", FieldKind::Float),
+        required("lock_x", FieldKind::Bool),
+        required("lock_y", FieldKind::Bool),
+        nullable("look_at", FieldKind::Point),
+        required("blend_seconds", FieldKind::Float),
+    ]),
+];
+
+pub fn schema(entity_identifier: &str) -> Option<&'static [FieldSpec]> {
+    ENTITY_SCHEMAS
+        .iter()
+        .find(|(identifier, _)| *identifier == entity_identifier)
+        .map(|(_, fields)| *fields)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldIssue {
+    Missing,
+    WrongType { found: String },
+    Null,
+    UnknownItem(String),
+}
+
+/// One thing wrong with an entity's fields, enough to find it in the editor
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldProblem {
+    pub level: String,
+    pub entity: String,
+    pub iid: String,
+    pub field: &'static str,
+    pub expected: FieldKind,
+    pub issue: FieldIssue,
+}
+
+impl fmt::Display for FieldProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "level {}, {} {}: field `{}` ", self.level, self.entity, self.iid, self.field)?;
+        match &self.issue {
+            FieldIssue::Missing => write!(f, "is missing, expected {}", self.expected.name()),
+            FieldIssue::WrongType { found } => write!(f, "is {}, expected {}", found, self.expected.name()),
+            FieldIssue::Null => write!(f, "is empty but can't be"),
+            FieldIssue::UnknownItem(item) => write!(f, "has unknown item {}", item),
+        }
+    }
+}
+
+/// `Some(has_null)` if the value is of the right kind
+fn check_value(kind: FieldKind, value: &FieldValue) -> Option<Result<bool, String>> {
+    let unknown_item = |item: &String| ItemType::from_str(item).err().map(|_| item.clone());
+    let checked = match (kind, value) {
+        (FieldKind::Int, FieldValue::Int(value)) => Ok(value.is_none()),
+        (FieldKind::Float, FieldValue::Float(value)) => Ok(value.is_none()),
+        (FieldKind::Bool, FieldValue::Bool(_)) => Ok(false),
+        (FieldKind::String, FieldValue::String(value)) => Ok(value.is_none()),
+        (FieldKind::Point, FieldValue::Point(value)) => Ok(value.is_none()),
+        (FieldKind::EntityRef, FieldValue::EntityRef(value)) => Ok(value.is_none()),
+        (FieldKind::Item, FieldValue::Enum(value)) => match value.as_ref().and_then(unknown_item) {
+            Some(item) => Err(item),
+            None => Ok(value.is_none()),
+        },
+        (FieldKind::Ints, FieldValue::Ints(values)) => Ok(values.iter().any(Option::is_none)),
+        (FieldKind::Points, FieldValue::Points(values)) => Ok(values.iter().any(Option::is_none)),
+        (FieldKind::Items, FieldValue::Enums(values)) => match values.iter().flatten().find_map(unknown_item) {
+            Some(item) => Err(item),
+            None => Ok(values.iter().any(Option::is_none)),
+        },
+        _ => return None,
+    };
+    Some(checked)
+}
+
+/// Checks one entity against its schema. Entities nothing is registered for are left alone.
+pub fn validate_entity(level: &str, entity_instance: &EntityInstance) -> Vec<FieldProblem> {
+    let Some(fields) = schema(&entity_instance.identifier) else { return Vec::new() };
+
+    fields
+        .iter()
+        .filter_map(|spec| {
+            let field = entity_instance
+                .field_instances
+                .iter()
+                .find(|field| field.identifier == spec.identifier);
+            let issue = match field {
+                None => FieldIssue::Missing,
+                Some(field) => match check_value(spec.kind, &field.value) {
+                    None => FieldIssue::WrongType { found: field.field_instance_type.clone() },
+                    Some(Err(item)) => FieldIssue::UnknownItem(item),
+                    Some(Ok(true)) if !spec.nullable => FieldIssue::Null,
+                    Some(Ok(_)) => return None,
+                },
+            };
+            Some(FieldProblem {
+                level: level.to_string(),
+                entity: entity_instance.identifier.clone(),
+                iid: entity_instance.iid.clone(),
+                field: spec.identifier,
+                expected: spec.kind,
+                issue,
+            })
+        })
+        .collect()
+}
+
+pub fn validate_project(project: &LdtkProject) -> Vec<FieldProblem> {
+    validate_levels(project.iter_raw_levels())
+}
+
+pub fn validate_levels<'a>(levels: impl IntoIterator<Item = &'a Level>) -> Vec<FieldProblem> {
+    levels
+        .into_iter()
+        .flat_map(|level| {
+            level
+                .layer_instances
+                .iter()
+                .flatten()
+                .flat_map(|layer| layer.entity_instances.iter())
+                .flat_map(|entity_instance| validate_entity(&level.identifier, entity_instance))
+        })
+        .collect()
+}
+
+/// Reports every bad field in a project at once, whenever it's loaded or edited.
+/// The bundles fall back to defaults for these instead of panicking.
+pub fn validate_loaded_projects(mut events: EventReader<AssetEvent<LdtkProject>>, projects: Res<Assets<LdtkProject>>) {
+    for event in events.read() {
+        let (AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id }) = event else { continue };
+        let Some(project) = projects.get(*id) else { continue };
+
+        let problems = validate_project(project);
+        if problems.is_empty() {
+            info!("LDtk entity fields are all valid");
+            continue;
+        }
+        let lines: Vec<String> = problems.iter().map(ToString::to_string).collect();
+        error!("{} problems with LDtk entity fields:\n  {}", problems.len(), lines.join("\n  "));
+    }
+}
+
+pub struct EntitySchemaPlugin;
+
+impl Plugin for EntitySchemaPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, validate_loaded_projects);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_every_missing_field_with_the_entity_iid() {
+        let npc = EntityInstance {
+            identifier: "NPC".to_string(),
+            iid: "npc-iid".to_string(),
+            ..Default::default()
+        };
+        let problems = validate_entity("Level_0", &npc);
+
+        assert_eq!(problems.len(), 2);
+        assert!(problems.iter().all(|problem| problem.issue == FieldIssue::Missing && problem.iid == "npc-iid"));
+        assert_eq!(
+            problems[0].to_string(),
+            "level Level_0, NPC npc-iid: field `name` is missing, expected String"
+        );
+
+        let decoration = EntityInstance { identifier: "Torch".to_string(), ..Default::default() };
+        assert!(validate_entity("Level_0", &decoration).is_empty());
+    }
+
+    #[test]
+    fn schemas_match_the_bundled_world() {
+        let project = crate::testing::world_json();
+
+        for (identifier, fields) in ENTITY_SCHEMAS {
+            let definition = project
+                .defs
+                .entities
+                .iter()
+                .find(|definition| definition.identifier == *identifier)
+                .unwrap_or_else(|| panic!("{} has a schema but no LDtk definition", identifier));
+            for spec in *fields {
+                assert!(
+                    definition.field_defs.iter().any(|field| field.identifier == spec.identifier),
+                    "{} has no `{}` field in LDtk",
+                    identifier,
+                    spec.identifier
+                );
+            }
+        }
+
+        let problems: Vec<String> = validate_levels(&project.levels).iter().map(ToString::to_string).collect();
+        assert!(problems.is_empty(), "bundled world has bad fields:\n  {}", problems.join("\n  "));
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// World facts that dialogue and quests can branch on.
+///
+/// Gameplay sets a few on its own:
+/// - `picked_up:<Item>` (bool) when an item is picked up, e.g. `picked_up:Simple_Shield`
+/// - `defeated:<boss>` (bool) when a boss dies, e.g. `defeated:golem`
+/// - `quest_started:<id>`, `quest_ready:<id>` and `quest_completed:<id>` (bool) as quests progress
+#[derive(Debug, Clone, Default, Resource, Serialize, Deserialize)]
+pub struct GameFlags {
+    pub bools: BTreeMap<String, bool>,
+    pub ints: BTreeMap<String, i32>,
+    pub strings: BTreeMap<String, String>,
+}
+
+impl GameFlags {
+    pub fn get_bool(&self, key: &str) -> bool {
+        self.bools.get(key).copied().unwrap_or(false)
+    }
+
+    pub fn get_int(&self, key: &str) -> i32 {
+        self.ints.get(key).copied().unwrap_or(0)
+    }
+
+    pub fn get_string(&self, key: &str) -> Option<&str> {
+        self.strings.get(key).map(String::as_str)
+    }
+
+    pub fn set_bool(&mut self, key: impl Into<String>, value: bool) {
+        self.bools.insert(key.into(), value);
+    }
+
+    pub fn set_int(&mut self, key: impl Into<String>, value: i32) {
+        self.ints.insert(key.into(), value);
+    }
+
+    pub fn add_int(&mut self, key: impl Into<String>, amount: i32) {
+        *self.ints.entry(key.into()).or_default() += amount;
+    }
+
+    pub fn set_string(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.strings.insert(key.into(), value.into());
+    }
+}
+
+/// A check against [GameFlags], written in dialogue files as e.g. `Flag("defeated:golem")`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub enum Condition {
+    Flag(String),
+    NotFlag(String),
+    IntAtLeast(String, i32),
+    IntBelow(String, i32),
+    StringEquals(String, String),
+}
+
+impl Condition {
+    pub fn holds(&self, flags: &GameFlags) -> bool {
+        match self {
+            Condition::Flag(key) => flags.get_bool(key),
+            Condition::NotFlag(key) => !flags.get_bool(key),
+            Condition::IntAtLeast(key, value) => flags.get_int(key) >= *value,
+            Condition::IntBelow(key, value) => flags.get_int(key) < *value,
+            Condition::StringEquals(key, value) => flags.get_string(key) == Some(value.as_str()),
+        }
+    }
+
+    pub fn all_hold(conditions: &[Condition], flags: &GameFlags) -> bool {
+        conditions.iter().all(|condition| condition.holds(flags))
+    }
+}
+
+pub struct FlagsPlugin;
+
+impl Plugin for FlagsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GameFlags>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conditions_read_flags() {
+        let mut flags = GameFlags::default();
+        flags.set_bool("picked_up:Simple_Shield", true);
+        flags.add_int("gold_spent", 5);
+        flags.add_int("gold_spent", 5);
+        flags.set_string("mood", "angry");
+
+        assert!(Condition::Flag("picked_up:Simple_Shield".into()).holds(&flags));
+        assert!(Condition::NotFlag("defeated:golem".into()).holds(&flags));
+        assert!(Condition::IntAtLeast("gold_spent".into(), 10).holds(&flags));
+        assert!(!Condition::IntBelow("gold_spent".into(), 10).holds(&flags));
+        assert!(Condition::StringEquals("mood".into(), "angry".into()).holds(&flags));
+        assert!(!Condition::all_hold(
+            &[Condition::Flag("picked_up:Simple_Shield".into()), Condition::Flag("missing".into())],
+            &flags,
+        ));
+    }
+}
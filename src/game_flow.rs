@@ -1,41 +1,124 @@
-use crate::{interaction::{handle_interaction_input, handle_interaction_prompts}, player::Player};
-use bevy::prelude::*;
+use crate::{actions::{Action, InputLock}, app_state::AppState, boss::BossFight, camera::FollowCamera, dialogue::DialogueRunner, flags::GameFlags, interaction::{handle_interaction_input, handle_interaction_prompts, interact_pressed}, level_transition::LevelEntered, map::ExploredLevels, player::Player, quests::QuestLog, world_state::WorldState};
+use bevy::{ecs::system::SystemParam, prelude::*};
 use bevy_ecs_ldtk::prelude::*;
 use bevy_rapier2d::prelude::*;
 
-pub fn setup(
-    mut commands: Commands,
-    asset_server: Res<AssetServer>,
-    mut rapier_config: Query<&mut RapierConfiguration>,
-) {
-    commands.spawn(Camera2d);
+pub const GRAVITY: Vec2 = Vec2::new(0.0, -2000.0);
+
+/// Each gets its own LDtk world. Level iids are unique, so every level is in exactly one of them.
+///
+/// Every world sits at the origin and places its levels at their LDtk world positions, so the
+/// files have to lay their levels out where they don't overlap. A new game starts in the first
+/// level of the first file.
+pub const WORLD_FILES: [&str; 1] = ["platformer-world.ldtk"];
+
+/// The project a new game starts and restarts in, [select_start_level] picks its first level
+#[derive(Debug, Resource)]
+pub struct StartWorld(pub Handle<LdtkProject>);
+
+/// Selects no level. Uids and indices restart in every project, so only an iid can pick
+/// one level out of several worlds, and none is known before a project has loaded.
+pub fn no_level() -> LevelSelection {
+    LevelSelection::iid("")
+}
+
+/// Looks levels up across every loaded LDtk project, instead of assuming there's exactly one
+#[derive(SystemParam)]
+pub struct LdtkProjects<'w, 's> {
+    handles: Query<'w, 's, (Entity, &'static LdtkProjectHandle)>,
+    assets: Res<'w, Assets<LdtkProject>>,
+}
+
+impl LdtkProjects<'_, '_> {
+    /// Loaded projects and the world entity each spawned into
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, &LdtkProject)> {
+        self.handles
+            .iter()
+            .filter_map(|(entity, handle)| self.assets.get(handle).map(|project| (entity, project)))
+    }
+
+    pub fn levels(&self) -> impl Iterator<Item = &Level> {
+        self.iter().flat_map(|(_, project)| project.iter_raw_levels())
+    }
+
+    pub fn level_by_iid(&self, iid: &str) -> Option<&Level> {
+        self.levels().find(|level| level.iid == iid)
+    }
+
+    /// The level `selection` points at, in the first project that has it
+    pub fn selected_level(&self, selection: &LevelSelection) -> Option<&Level> {
+        self.iter().find_map(|(_, project)| {
+            project
+                .iter_raw_levels()
+                .enumerate()
+                .find(|(index, level)| selection.is_match(&LevelIndices { world: None, level: *index }, level))
+                .map(|(_, level)| level)
+        })
+    }
+}
+
+pub fn setup(mut commands: Commands, mut rapier_config: Query<&mut RapierConfiguration>) {
+    commands.spawn((Camera2d, Msaa::Off, FollowCamera::default()));
+
+    rapier_config.single_mut().gravity = GRAVITY;
+}
+
+/// Forgets everything the last run did, shared by new games and restarts
+fn reset_progress(commands: &mut Commands) {
+    commands.insert_resource(GameFlags::default());
+    commands.insert_resource(QuestLog::default());
+    commands.insert_resource(WorldState::default());
+    commands.insert_resource(ExploredLevels::default());
+    commands.insert_resource(BossFight::default());
+    commands.insert_resource(DialogueRunner::default());
+    commands.insert_resource(InputLock::default());
+    commands.insert_resource(no_level());
+}
 
-    rapier_config.single_mut().gravity = Vec2::new(0.0, -2000.0);
+/// Starts a new game from the title menu
+pub fn spawn_world(mut commands: Commands, asset_server: Res<AssetServer>) {
+    reset_progress(&mut commands);
 
-    let ldtk_handle = asset_server
-        .load("platformer-world.ldtk")
-        .into();
-    commands.spawn(LdtkWorldBundle {
-        ldtk_handle,
-        ..Default::default()
-    });
+    for (index, file) in WORLD_FILES.into_iter().enumerate() {
+        let handle: Handle<LdtkProject> = asset_server.load(file);
+        if index == 0 {
+            commands.insert_resource(StartWorld(handle.clone()));
+        }
+        commands.spawn(LdtkWorldBundle {
+            ldtk_handle: handle.into(),
+            ..Default::default()
+        });
+    }
+}
+
+/// Selects the first level of [StartWorld] whenever nothing is selected and its project has loaded
+pub fn select_start_level(
+    start_world: Res<StartWorld>,
+    projects: Res<Assets<LdtkProject>>,
+    mut level_selection: ResMut<LevelSelection>,
+) {
+    if *level_selection != no_level() {
+        return;
+    }
+    let Some(project) = projects.get(&start_world.0) else { return };
+    match project.iter_raw_levels().next() {
+        Some(level) => *level_selection = LevelSelection::iid(level.iid.clone()),
+        None => error_once!("{} has no levels to start in", WORLD_FILES[0]),
+    }
 }
 
 pub fn update_level_selection(
     level_query: Query<(&LevelIid, &Transform), Without<Player>>,
     player_query: Query<&Transform, With<Player>>,
     mut level_selection: ResMut<LevelSelection>,
-    ldtk_projects: Query<&LdtkProjectHandle>,
-    ldtk_project_assets: Res<Assets<LdtkProject>>,
+    ldtk_projects: LdtkProjects,
+    mut level_entered: EventWriter<LevelEntered>,
 ) {
     for (level_iid, level_transform) in &level_query {
-        let ldtk_project = ldtk_project_assets
-            .get(ldtk_projects.single())
-            .expect("Project should be loaded if level has spawned");
-
-        let level = ldtk_project
-            .get_raw_level_by_iid(&level_iid.to_string())
-            .expect("Spawned level should exist in LDtk project");
+        let Some(level) = ldtk_projects.level_by_iid(&level_iid.to_string()) else {
+            warn_once!("Spawned level {} isn't in any loaded LDtk project", level_iid);
+            continue;
+        };
 
         let level_bounds = Rect {
             min: Vec2::new(level_transform.translation.x, level_transform.translation.y),
@@ -53,6 +136,7 @@ pub fn update_level_selection(
                 && !level_selection.is_match(&LevelIndices::default(), level)
             {
                 *level_selection = LevelSelection::iid(level.iid.clone());
+                level_entered.send(LevelEntered { iid: level.iid.clone() });
             }
         }
     }
@@ -70,13 +154,30 @@ pub fn update_level_selection(
 //     }
 // }
 
+pub fn despawn_world(mut commands: Commands, ldtk_projects: Query<Entity, With<LdtkProjectHandle>>) {
+    for entity in ldtk_projects.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<StartWorld>();
+    // A conversation left open when quitting shouldn't follow into the next game
+    commands.insert_resource(DialogueRunner::default());
+}
+
+/// Starts over from the first level, with everything the run did forgotten
+pub fn restart_world(commands: &mut Commands, ldtk_projects: &Query<Entity, With<LdtkProjectHandle>>) {
+    reset_progress(commands);
+    for entity in ldtk_projects.iter() {
+        commands.entity(entity).insert(Respawn);
+    }
+}
+
 fn respawn_world(
     mut commands: Commands,
     ldtk_projects: Query<Entity, With<LdtkProjectHandle>>,
-    input: Res<ButtonInput<KeyCode>>,
+    actions: Res<ButtonInput<Action>>,
 ) {
-    if input.just_pressed(KeyCode::KeyR) {
-        commands.entity(ldtk_projects.single()).insert(Respawn);
+    if actions.just_pressed(Action::Restart) {
+        restart_world(&mut commands, &ldtk_projects);
     }
 }
 
@@ -85,8 +186,14 @@ pub struct GameFlowPlugin;
 impl Plugin for GameFlowPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(Startup, setup)
-            .add_systems(Update, update_level_selection)
-            .add_systems(Update, respawn_world)
-            .add_systems(Update, (handle_interaction_prompts, handle_interaction_input));
+            .add_systems(OnEnter(AppState::Loading), spawn_world)
+            .add_systems(OnEnter(AppState::TitleMenu), despawn_world)
+            .add_systems(Update, select_start_level.run_if(resource_exists::<StartWorld>))
+            .add_systems(Update, (
+                update_level_selection,
+                respawn_world,
+                handle_interaction_prompts,
+                handle_interaction_input.run_if(interact_pressed),
+            ).run_if(in_state(AppState::Playing)));
     }
 }
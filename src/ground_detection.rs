@@ -4,6 +4,8 @@ use bevy::prelude::*;
 
 use bevy_rapier2d::prelude::*;
 
+use crate::app_state::AppState;
+
 #[derive(Component)]
 pub struct GroundSensor {
     pub ground_detection_entity: Entity,
@@ -96,8 +98,6 @@ pub struct GroundDetectionPlugin;
 
 impl Plugin for GroundDetectionPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, spawn_ground_sensor)
-            .add_systems(Update, ground_detection)
-            .add_systems(Update, update_on_ground);
+        app.add_systems(Update, (spawn_ground_sensor, ground_detection, update_on_ground).run_if(in_state(AppState::Playing)));
     }
 }
@@ -1,15 +1,61 @@
 use bevy::prelude::*;
 use bevy_ecs_ldtk::prelude::*;
 
+/// For entities whose `health` field is missing or empty
+const DEFAULT_HEALTH: i32 = 100;
+
 #[derive(Debug, Default, Component, Reflect)]
-pub struct Health(i32);
+pub struct Health {
+    current: i32,
+    max: i32,
+}
 
 impl Health {
+    pub fn new(amount: i32) -> Health {
+        Health {
+            current: amount,
+            max: amount,
+        }
+    }
+
+    pub fn current(&self) -> i32 {
+        self.current
+    }
+
+    /// What the entity spawned with from LDtk
+    pub fn max(&self) -> i32 {
+        self.max
+    }
+
+    pub fn fraction(&self) -> f32 {
+        if self.max <= 0 {
+            return 0.;
+        }
+        self.current as f32 / self.max as f32
+    }
+
+    /// Sets the current health, e.g. from a save, without going over the max
+    pub fn set_current(&mut self, amount: i32) {
+        self.current = amount.clamp(0, self.max);
+    }
+
+    pub fn damage(&mut self, amount: i32) {
+        self.current = (self.current - amount).max(0);
+    }
+
+    pub fn heal(&mut self, amount: i32) {
+        self.current = (self.current + amount).min(self.max);
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.current >= self.max
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.current <= 0
+    }
+
     pub fn from_field(entity_instance: &EntityInstance) -> Health {
-        Health(
-            *entity_instance
-                .get_int_field("health")
-                .expect("expected entity to have non-nullable health int field"),
-        )
+        Health::new(entity_instance.get_int_field("health").copied().unwrap_or(DEFAULT_HEALTH))
     }
-}
\ No newline at end of file
+}
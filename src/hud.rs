@@ -0,0 +1,206 @@
+use bevy::prelude::*;
+
+use crate::{
+    app_state::AppState,
+    health::Health,
+    inventory::{Equipment, Inventory},
+    items::ItemType,
+    player::Player,
+    stamina::Stamina,
+};
+
+const ICONS_PATH: &str = "icons_16x16.png";
+const ICON_COLUMNS: u32 = 16;
+const ICON_ROWS: u32 = 22;
+const ICON_SIZE: f32 = 24.;
+
+const BAR_WIDTH: f32 = 160.;
+const BAR_HEIGHT: f32 = 10.;
+
+/// The icon sheet, shared by everything that shows items in the UI
+#[derive(Debug, Default, Resource)]
+pub struct Icons {
+    pub image: Handle<Image>,
+    pub layout: Handle<TextureAtlasLayout>,
+}
+
+impl Icons {
+    pub fn node(&self, index: usize) -> ImageNode {
+        ImageNode::from_atlas_image(
+            self.image.clone(),
+            TextureAtlas {
+                layout: self.layout.clone(),
+                index,
+            },
+        )
+    }
+}
+
+#[derive(Component)]
+pub struct HealthBar;
+
+#[derive(Component)]
+pub struct StaminaBar;
+
+#[derive(Component)]
+pub struct GoldText;
+
+#[derive(Component)]
+pub struct EquipmentIcons;
+
+pub fn load_icons(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut layouts: ResMut<Assets<TextureAtlasLayout>>,
+) {
+    commands.insert_resource(Icons {
+        image: asset_server.load(ICONS_PATH),
+        layout: layouts.add(TextureAtlasLayout::from_grid(UVec2::splat(16), ICON_COLUMNS, ICON_ROWS, None, None)),
+    });
+}
+
+fn spawn_bar(parent: &mut ChildBuilder, color: Color, marker: impl Component) {
+    parent
+        .spawn((
+            Node {
+                width: Val::Px(BAR_WIDTH),
+                height: Val::Px(BAR_HEIGHT),
+                margin: UiRect::bottom(Val::Px(4.0)),
+                ..Default::default()
+            },
+            BackgroundColor::from(Color::srgba(0.1, 0.1, 0.1, 0.8)),
+        ))
+        .with_children(|bar| {
+            bar.spawn((
+                Node {
+                    width: Val::Percent(100.),
+                    height: Val::Percent(100.),
+                    ..Default::default()
+                },
+                BackgroundColor::from(color),
+                marker,
+            ));
+        });
+}
+
+pub fn spawn_hud(mut commands: Commands, icons: Res<Icons>) {
+    commands
+        .spawn(Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(20.0),
+            left: Val::Px(20.0),
+            flex_direction: FlexDirection::Column,
+            ..Default::default()
+        })
+        .with_children(|parent| {
+            spawn_bar(parent, Color::srgb(0.8, 0.15, 0.15), HealthBar);
+            spawn_bar(parent, Color::srgb(0.3, 0.75, 0.3), StaminaBar);
+
+            parent
+                .spawn(Node {
+                    align_items: AlignItems::Center,
+                    column_gap: Val::Px(4.0),
+                    ..Default::default()
+                })
+                .with_children(|row| {
+                    row.spawn((
+                        icons.node(ItemType::Gold.icon()),
+                        Node {
+                            width: Val::Px(ICON_SIZE),
+                            height: Val::Px(ICON_SIZE),
+                            ..Default::default()
+                        },
+                    ));
+                    row.spawn((
+                        Text::new("0"),
+                        TextFont {
+                            font_size: 18.0,
+                            ..Default::default()
+                        },
+                        TextColor(Color::WHITE),
+                        GoldText,
+                    ));
+                });
+
+            parent.spawn((
+                Node {
+                    column_gap: Val::Px(4.0),
+                    ..Default::default()
+                },
+                EquipmentIcons,
+            ));
+        });
+}
+
+// Each of these only runs when what it shows has changed
+
+pub fn update_health_bar(
+    player_query: Query<&Health, (With<Player>, Changed<Health>)>,
+    mut bar_query: Query<&mut Node, With<HealthBar>>,
+) {
+    let Ok(health) = player_query.get_single() else { return };
+    for mut node in bar_query.iter_mut() {
+        node.width = Val::Percent(health.fraction() * 100.);
+    }
+}
+
+pub fn update_stamina_bar(
+    player_query: Query<&Stamina, (With<Player>, Changed<Stamina>)>,
+    mut bar_query: Query<(&mut Node, &mut BackgroundColor), With<StaminaBar>>,
+) {
+    let Ok(stamina) = player_query.get_single() else { return };
+    for (mut node, mut color) in bar_query.iter_mut() {
+        node.width = Val::Percent(stamina.fraction() * 100.);
+        // Greyed out until it's recovered enough to block again
+        color.0 = if stamina.exhausted {
+            Color::srgb(0.45, 0.45, 0.45)
+        } else {
+            Color::srgb(0.3, 0.75, 0.3)
+        };
+    }
+}
+
+pub fn update_gold_text(
+    player_query: Query<&Inventory, (With<Player>, Changed<Inventory>)>,
+    mut text_query: Query<&mut Text, With<GoldText>>,
+) {
+    let Ok(inventory) = player_query.get_single() else { return };
+    for mut text in text_query.iter_mut() {
+        text.0 = inventory.count(ItemType::Gold).to_string();
+    }
+}
+
+pub fn update_equipment_icons(
+    mut commands: Commands,
+    icons: Res<Icons>,
+    player_query: Query<&Equipment, (With<Player>, Changed<Equipment>)>,
+    row_query: Query<Entity, With<EquipmentIcons>>,
+) {
+    let Ok(equipment) = player_query.get_single() else { return };
+    let icon_indices: Vec<usize> = equipment.slots.values().map(|item_type| item_type.icon()).collect();
+
+    for row in row_query.iter() {
+        commands.entity(row).despawn_descendants().with_children(|row| {
+            for index in icon_indices.iter() {
+                row.spawn((
+                    icons.node(*index),
+                    Node {
+                        width: Val::Px(ICON_SIZE),
+                        height: Val::Px(ICON_SIZE),
+                        ..Default::default()
+                    },
+                ));
+            }
+        });
+    }
+}
+
+pub struct HudPlugin;
+
+impl Plugin for HudPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Startup, (load_icons, spawn_hud).chain())
+            .add_systems(Update, (update_health_bar, update_stamina_bar, update_gold_text, update_equipment_icons).run_if(in_state(AppState::Playing)));
+    }
+}
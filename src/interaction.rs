@@ -1,7 +1,8 @@
-use bevy::prelude::*;
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_ecs_ldtk::prelude::*;
 use bevy_rapier2d::prelude::*;
 
-use crate::{abilities::Abilities, dialogue::DialogueUI, items::{Item, ItemType}, npc::NPC, player::Player};
+use crate::{doors::{Door, UseDoor}, actions::{Action, ActionBindings, InputBinding}, dialogue::{DialogueHandle, DialogueRunner, StartDialogue}, flags::GameFlags, inventory::{Equipment, Inventory}, items::{Item, ItemCollected}, locale::LocalizedText, npc::NPC, player::Player, shop::{OpenShop, Shop}, world_state::{EntityState, WorldState}};
 
 #[derive(Component)]
 pub struct InteractionPrompt {
@@ -13,6 +14,33 @@ pub struct InteractionPrompt {
 pub enum PromptType {
     Dialogue,
     Pickup,
+    Shop,
+    Door,
+}
+
+/// Everything the player can walk up to and use, found through the parent of its sensor
+#[derive(SystemParam)]
+pub struct Interactables<'w, 's> {
+    pub npcs: Query<'w, 's, (&'static Name, Option<&'static DialogueHandle>), With<NPC>>,
+    pub items: Query<'w, 's, (&'static Item, Option<&'static EntityIid>)>,
+    pub shops: Query<'w, 's, (), With<Shop>>,
+    pub doors: Query<'w, 's, (), With<Door>>,
+}
+
+impl Interactables<'_, '_> {
+    pub fn prompt_type(&self, entity: Entity) -> Option<PromptType> {
+        if self.npcs.contains(entity) {
+            Some(PromptType::Dialogue)
+        } else if self.items.contains(entity) {
+            Some(PromptType::Pickup)
+        } else if self.shops.contains(entity) {
+            Some(PromptType::Shop)
+        } else if self.doors.contains(entity) {
+            Some(PromptType::Door)
+        } else {
+            None
+        }
+    }
 }
 
 // System to spawn and position interaction prompts
@@ -21,29 +49,34 @@ pub fn handle_interaction_prompts(
     player_query: Query<Entity, With<Player>>,
     sensor_query: Query<(&Parent, &CollisionGroups)>,
     mut collision_events: EventReader<CollisionEvent>,
-    item_query: Query<Entity, With<Item>>,
-    npc_query: Query<Entity, With<NPC>>,
+    interactables: Interactables,
     prompt_query: Query<(Entity, &InteractionPrompt)>,
+    bindings: Res<ActionBindings>,
 ) {
     // Handle prompt spawning/positioning
     for event in collision_events.read() {
         match event {
             CollisionEvent::Started(e1, e2, _) => {
                 if player_query.get(*e1).is_ok() || player_query.get(*e2).is_ok() {
-                    // Check if collision is with an NPC or Item sensor
+                    // Check if collision is with an NPC, Item, Shop or Door sensor
                     if let Ok((parent, _)) = sensor_query.get(*e1) {
                         let parent_entity = parent.get();
 
-                        // Determine prompt type
-                        let prompt_type = if npc_query.get(parent_entity).is_ok() {
-                            Some(PromptType::Dialogue)
-                        } else if item_query.get(parent_entity).is_ok() {
-                            Some(PromptType::Pickup)
-                        } else {
-                            None
-                        };
+                        if let Some(prompt_type) = interactables.prompt_type(parent_entity) {
+                            let interact_key = bindings
+                                .get(Action::Interact)
+                                .first()
+                                .map(InputBinding::label)
+                                .unwrap_or_default();
+                            let text = match (&prompt_type, interactables.items.get(parent_entity)) {
+                                (_, Ok((item, _))) => LocalizedText::new("prompt.pickup")
+                                    .with_arg("key", interact_key)
+                                    .with_arg("item", item.item_type.name_key()),
+                                (PromptType::Shop, _) => LocalizedText::new("prompt.shop").with_arg("key", interact_key),
+                                (PromptType::Door, _) => LocalizedText::new("prompt.door").with_arg("key", interact_key),
+                                _ => LocalizedText::new("prompt.interact").with_arg("key", interact_key),
+                            };
 
-                        if let Some(prompt_type) = prompt_type {
                             // Spawn prompt UI
                             println!("Spawning prompt");
                             commands
@@ -61,7 +94,8 @@ pub fn handle_interaction_prompts(
                                 ))
                                 .with_children(|parent| {
                                     parent.spawn((
-                                        Text::new("Press E to interact"),
+                                        Text::default(),
+                                        text,
                                         TextFont {
                                             font_size: 16.0,
                                             ..Default::default()
@@ -83,47 +117,74 @@ pub fn handle_interaction_prompts(
     }
 }
 
+/// Interact advances the conversation instead while one is open
+pub fn interact_pressed(actions: Res<ButtonInput<Action>>, dialogue_runner: Res<DialogueRunner>) -> bool {
+    actions.just_pressed(Action::Interact) && !dialogue_runner.is_active()
+}
+
+/// Everything an interaction can set off
+#[derive(SystemParam)]
+pub struct InteractionEvents<'w> {
+    start_dialogue: EventWriter<'w, StartDialogue>,
+    collected: EventWriter<'w, ItemCollected>,
+    open_shop: EventWriter<'w, OpenShop>,
+    use_door: EventWriter<'w, UseDoor>,
+}
+
 // System to handle interaction input
 pub fn handle_interaction_input(
     mut commands: Commands,
-    input: Res<ButtonInput<KeyCode>>,
     prompt_query: Query<(&InteractionPrompt, Entity)>,
-    mut player_query: Query<&mut Abilities, With<Player>>,
-    item_query: Query<(Entity, &Item)>,
-    mut dialogue_ui_query: Query<&mut DialogueUI>,
-    // mut inventory: ResMut<Inventory>, // You'll need to create this resource
+    mut player_query: Query<(&mut Equipment, &mut Inventory), With<Player>>,
+    interactables: Interactables,
+    mut flags: ResMut<GameFlags>,
+    mut world_state: ResMut<WorldState>,
+    mut events: InteractionEvents,
 ) {
-    if input.just_pressed(KeyCode::KeyE) {
-        for (prompt, prompt_entity) in prompt_query.iter() {
-            match prompt.prompt_type {
-                PromptType::Dialogue => {
-                    // Handle dialogue activation
-                    if let Ok(mut dialogue_ui) = dialogue_ui_query.get_single_mut() {
-                        dialogue_ui.active = true;
-                    }
+    for (prompt, prompt_entity) in prompt_query.iter() {
+        match prompt.prompt_type {
+            PromptType::Dialogue => {
+                if let Ok((name, Some(dialogue))) = interactables.npcs.get(prompt.entity) {
+                    events.start_dialogue.send(StartDialogue {
+                        graph: dialogue.0.clone(),
+                        speaker: name.to_string(),
+                    });
                 }
-                PromptType::Pickup => {
-                    // Handle item pickup
-                    // Add to inventory
-                    // inventory.items.push(...);
-                    
-                    if let Ok((entity, item)) = item_query.get(prompt.entity) {
-                        if let Ok(mut abilities) = player_query.get_single_mut() {
-                            match item.item_type {
-                                ItemType::SimpleShield => {
-                                    abilities.unlock_block();
-                                    println!("Unlocked block ability!");
-                                }
-                                // Handle other item types
+                // Keep the prompt so the NPC can be talked to again
+                continue;
+            }
+            PromptType::Shop => {
+                events.open_shop.send(OpenShop { shop: prompt.entity });
+                continue;
+            }
+            PromptType::Door => {
+                events.use_door.send(UseDoor { door: prompt.entity });
+            }
+            PromptType::Pickup => {
+                if let Ok((item, iid)) = interactables.items.get(prompt.entity) {
+                    if let Ok((mut equipment, mut inventory)) = player_query.get_single_mut() {
+                        inventory.add(item.item_type, item.count);
+                        // Wear it straight away if there's nothing in its slot yet
+                        if let Some(slot) = item.item_type.equip_slot() {
+                            if equipment.get(slot).is_none() && equipment.equip(&mut inventory, item.item_type) {
+                                info!("Equipped {:?}", item.item_type);
                             }
-                            // Despawn the item after pickup
-                            commands.entity(entity).despawn_recursive();
                         }
+                        events.collected.send(ItemCollected {
+                            item_type: item.item_type,
+                            count: item.count,
+                        });
+                        flags.set_bool(format!("picked_up:{}", item.item_type.identifier()), true);
+                        // Despawn the item after pickup, and keep it gone when its level reloads
+                        if let Some(iid) = iid {
+                            world_state.record(iid, EntityState::Collected);
+                        }
+                        commands.entity(prompt.entity).despawn_recursive();
                     }
                 }
             }
-            // Remove the prompt
-            commands.entity(prompt_entity).despawn_recursive();
         }
+        // Remove the prompt
+        commands.entity(prompt_entity).despawn_recursive();
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,530 @@
+use std::{collections::BTreeMap, str::FromStr};
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_ecs_ldtk::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    actions::{Action, ActionBindings, InputBinding, InputLock, Menu},
+    app_state::AppState,
+    colliders::ItemColliderBundle,
+    health::Health,
+    hud::Icons,
+    items::{Item, ItemType},
+    locale::Localizer,
+    player::Player,
+};
+
+/// What the player is carrying, seeded from the LDtk `inventory` field
+#[derive(Debug, Clone, Default, Component, Serialize, Deserialize)]
+pub struct Inventory {
+    pub items: BTreeMap<ItemType, u32>,
+}
+
+impl Inventory {
+    pub fn from_field(entity_instance: &EntityInstance) -> Inventory {
+        let mut inventory = Inventory::default();
+        // Bad fields are reported by the entity schema check, start empty-handed instead
+        for item in entity_instance.iter_enums_field("inventory").into_iter().flatten() {
+            if let Ok(item_type) = ItemType::from_str(item) {
+                inventory.add(item_type, 1);
+            }
+        }
+        inventory
+    }
+
+    pub fn add(&mut self, item_type: ItemType, count: u32) {
+        *self.items.entry(item_type).or_default() += count;
+    }
+
+    /// Removes `count` of an item, failing without change if there aren't enough
+    pub fn remove(&mut self, item_type: ItemType, count: u32) -> bool {
+        match self.items.get_mut(&item_type) {
+            Some(held) if *held >= count => {
+                *held -= count;
+                if *held == 0 {
+                    self.items.remove(&item_type);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn count(&self, item_type: ItemType) -> u32 {
+        self.items.get(&item_type).copied().unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum EquipSlot {
+    OffHand,
+}
+
+impl EquipSlot {
+    pub const ALL: [EquipSlot; 1] = [EquipSlot::OffHand];
+
+    pub fn name_key(&self) -> &'static str {
+        match self {
+            EquipSlot::OffHand => "inventory.slot.OffHand",
+        }
+    }
+}
+
+/// What the player is wearing. Equipped items are taken out of the [Inventory].
+#[derive(Debug, Clone, Default, Component, Serialize, Deserialize)]
+pub struct Equipment {
+    pub slots: BTreeMap<EquipSlot, ItemType>,
+}
+
+impl Equipment {
+    pub fn get(&self, slot: EquipSlot) -> Option<ItemType> {
+        self.slots.get(&slot).copied()
+    }
+
+    pub fn count(&self, item_type: ItemType) -> u32 {
+        self.slots.values().filter(|worn| **worn == item_type).count() as u32
+    }
+
+    /// Moves one `item_type` from the inventory into its slot, putting back whatever was there
+    pub fn equip(&mut self, inventory: &mut Inventory, item_type: ItemType) -> bool {
+        let Some(slot) = item_type.equip_slot() else { return false };
+        if !inventory.remove(item_type, 1) {
+            return false;
+        }
+        if let Some(previous) = self.slots.insert(slot, item_type) {
+            inventory.add(previous, 1);
+        }
+        true
+    }
+
+    pub fn unequip(&mut self, inventory: &mut Inventory, slot: EquipSlot) -> bool {
+        match self.slots.remove(&slot) {
+            Some(item_type) => {
+                inventory.add(item_type, 1);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn can_block(&self) -> bool {
+        self.get(EquipSlot::OffHand) == Some(ItemType::SimpleShield)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ItemAction {
+    Use,
+    Equip,
+    Unequip,
+    Drop,
+}
+
+impl ItemAction {
+    pub fn name_key(&self) -> &'static str {
+        match self {
+            ItemAction::Use => "inventory.use",
+            ItemAction::Equip => "inventory.equip",
+            ItemAction::Unequip => "inventory.unequip",
+            ItemAction::Drop => "inventory.drop",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InventoryRow {
+    Slot(EquipSlot, Option<ItemType>),
+    Item(ItemType, u32),
+}
+
+impl InventoryRow {
+    /// Equipment slots first, then everything carried
+    pub fn all(inventory: &Inventory, equipment: &Equipment) -> Vec<InventoryRow> {
+        let slots = EquipSlot::ALL.into_iter().map(|slot| InventoryRow::Slot(slot, equipment.get(slot)));
+        let items = inventory.items.iter().map(|(&item_type, &count)| InventoryRow::Item(item_type, count));
+        slots.chain(items).collect()
+    }
+
+    pub fn item_type(&self) -> Option<ItemType> {
+        match *self {
+            InventoryRow::Slot(_, item_type) => item_type,
+            InventoryRow::Item(item_type, _) => Some(item_type),
+        }
+    }
+
+    pub fn actions(&self) -> Vec<ItemAction> {
+        match *self {
+            InventoryRow::Slot(_, Some(_)) => vec![ItemAction::Unequip],
+            InventoryRow::Slot(_, None) => Vec::new(),
+            InventoryRow::Item(item_type, _) => {
+                let mut actions = Vec::new();
+                if item_type.heal_amount().is_some() {
+                    actions.push(ItemAction::Use);
+                }
+                if item_type.equip_slot().is_some() {
+                    actions.push(ItemAction::Equip);
+                }
+                if item_type != ItemType::Gold {
+                    actions.push(ItemAction::Drop);
+                }
+                actions
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, Resource)]
+pub struct InventoryScreen {
+    pub open: bool,
+    pub selected: usize,
+    /// Cursor in the selected row's actions, while picking one
+    pub action: Option<usize>,
+    /// String table key of the last action's result
+    pub message: Option<&'static str>,
+}
+
+#[derive(Component)]
+pub struct InventoryUI;
+
+#[derive(Component)]
+pub struct InventoryList;
+
+#[derive(Component)]
+pub struct InventoryDetails;
+
+pub fn spawn_inventory_ui(mut commands: Commands) {
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(60.0),
+                left: Val::Percent(50.0),
+                margin: UiRect::left(Val::Px(-220.0)),
+                width: Val::Px(440.0),
+                padding: UiRect::all(Val::Px(10.0)),
+                column_gap: Val::Px(12.0),
+                ..Default::default()
+            },
+            Visibility::Hidden,
+            BackgroundColor::from(Color::srgba(0.2, 0.2, 0.2, 0.9)),
+            GlobalZIndex(5),
+            InventoryUI,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Node {
+                    width: Val::Px(220.0),
+                    flex_direction: FlexDirection::Column,
+                    row_gap: Val::Px(2.0),
+                    ..Default::default()
+                },
+                InventoryList,
+            ));
+            parent.spawn((
+                Text::new(""),
+                TextFont {
+                    font_size: 16.0,
+                    ..Default::default()
+                },
+                TextColor(Color::WHITE),
+                Node {
+                    flex_grow: 1.,
+                    ..Default::default()
+                },
+                InventoryDetails,
+            ));
+        });
+}
+
+/// Drops one item at the player's feet, in the same parent so it goes away with the world
+fn spawn_dropped_item(commands: &mut Commands, icons: &Icons, item_type: ItemType, transform: &Transform, parent: Option<&Parent>) {
+    let item = commands
+        .spawn((
+            Sprite::from_atlas_image(
+                icons.image.clone(),
+                TextureAtlas {
+                    layout: icons.layout.clone(),
+                    index: item_type.icon(),
+                },
+            ),
+            Transform::from_translation(transform.translation + Vec3::Y * 8.),
+            Item { item_type, count: 1 },
+            ItemColliderBundle::item(),
+        ))
+        .id();
+    if let Some(parent) = parent {
+        commands.entity(parent.get()).add_child(item);
+    }
+}
+
+/// What the open screen takes over from the game underneath
+#[derive(SystemParam)]
+pub struct MenuPause<'w> {
+    lock: ResMut<'w, InputLock>,
+    virtual_time: ResMut<'w, Time<Virtual>>,
+}
+
+#[allow(clippy::type_complexity)]
+pub fn handle_inventory_input(
+    mut commands: Commands,
+    actions: Res<ButtonInput<Action>>,
+    mut screen: ResMut<InventoryScreen>,
+    mut pause: MenuPause,
+    icons: Res<Icons>,
+    mut player_query: Query<(&mut Inventory, &mut Equipment, &mut Health, &Transform, Option<&Parent>), With<Player>>,
+    mut ui_query: Query<&mut Visibility, With<InventoryUI>>,
+) {
+    let closing = screen.open
+        && (actions.just_pressed(Action::ToggleInventory) || (screen.action.is_none() && actions.just_pressed(Action::MenuBack)));
+    // Stays shut while another menu has the controls
+    let opening = !screen.open && actions.just_pressed(Action::ToggleInventory) && pause.lock.open_menu(Menu::Inventory);
+    if opening || closing {
+        *screen = InventoryScreen {
+            open: opening,
+            ..Default::default()
+        };
+        if closing {
+            pause.lock.close_menu(Menu::Inventory);
+        }
+        // Pauses the game underneath, hit stop keeps its own speed
+        if opening {
+            pause.virtual_time.pause();
+        } else {
+            pause.virtual_time.unpause();
+        }
+        for mut visibility in ui_query.iter_mut() {
+            *visibility = if opening { Visibility::Visible } else { Visibility::Hidden };
+        }
+        return;
+    }
+    if !screen.open {
+        return;
+    }
+
+    let Ok((mut inventory, mut equipment, mut health, transform, parent)) = player_query.get_single_mut() else { return };
+    let rows = InventoryRow::all(&inventory, &equipment);
+    let Some(row) = rows.get(screen.selected).copied() else {
+        screen.selected = 0;
+        return;
+    };
+    let row_actions = row.actions();
+
+    let Some(action) = screen.action else {
+        if actions.just_pressed(Action::MenuUp) {
+            screen.selected = (screen.selected + rows.len() - 1) % rows.len();
+        }
+        if actions.just_pressed(Action::MenuDown) {
+            screen.selected = (screen.selected + 1) % rows.len();
+        }
+        if actions.just_pressed(Action::MenuConfirm) && !row_actions.is_empty() {
+            screen.action = Some(0);
+            screen.message = None;
+        }
+        return;
+    };
+
+    if actions.just_pressed(Action::MenuBack) {
+        screen.action = None;
+        return;
+    }
+    if actions.just_pressed(Action::MenuUp) {
+        screen.action = Some((action + row_actions.len() - 1) % row_actions.len());
+    }
+    if actions.just_pressed(Action::MenuDown) {
+        screen.action = Some((action + 1) % row_actions.len());
+    }
+    if !actions.just_pressed(Action::MenuConfirm) {
+        return;
+    }
+
+    let Some(item_type) = row.item_type() else { return };
+    match row_actions[action] {
+        ItemAction::Use => {
+            if health.is_full() {
+                screen.message = Some("inventory.full_health");
+            } else if inventory.remove(item_type, 1) {
+                health.heal(item_type.heal_amount().unwrap_or(0));
+                screen.message = Some("inventory.used");
+            }
+        }
+        ItemAction::Equip => {
+            equipment.equip(&mut inventory, item_type);
+        }
+        ItemAction::Unequip => {
+            if let InventoryRow::Slot(slot, _) = row {
+                equipment.unequip(&mut inventory, slot);
+            }
+        }
+        ItemAction::Drop => {
+            if inventory.remove(item_type, 1) {
+                spawn_dropped_item(&mut commands, &icons, item_type, transform, parent);
+            }
+        }
+    }
+    screen.action = None;
+    // Using or dropping the last of something removes its row
+    let len = InventoryRow::all(&inventory, &equipment).len();
+    screen.selected = screen.selected.min(len.saturating_sub(1));
+}
+
+/// The parts of the inventory screen that get rebuilt
+#[derive(SystemParam)]
+pub struct InventoryWidgets<'w, 's> {
+    list_query: Query<'w, 's, Entity, With<InventoryList>>,
+    text_query: Query<'w, 's, &'static mut Text, With<InventoryDetails>>,
+}
+
+pub fn update_inventory_ui(
+    mut commands: Commands,
+    screen: Res<InventoryScreen>,
+    player_query: Query<(Ref<Inventory>, Ref<Equipment>), With<Player>>,
+    icons: Res<Icons>,
+    bindings: Res<ActionBindings>,
+    localizer: Localizer,
+    mut widgets: InventoryWidgets,
+) {
+    if !screen.open {
+        return;
+    }
+    let Ok((inventory, equipment)) = player_query.get_single() else { return };
+    if !screen.is_changed() && !inventory.is_changed() && !equipment.is_changed() && !localizer.changed() {
+        return;
+    }
+
+    let rows = InventoryRow::all(&inventory, &equipment);
+    for list in widgets.list_query.iter() {
+        commands.entity(list).despawn_descendants().with_children(|parent| {
+            parent.spawn((
+                Text::new(localizer.get("inventory.title")),
+                TextFont {
+                    font_size: 18.0,
+                    ..Default::default()
+                },
+                TextColor(Color::WHITE),
+            ));
+            for (index, row) in rows.iter().enumerate() {
+                let label = match *row {
+                    InventoryRow::Slot(slot, item_type) => format!(
+                        "{}: {}",
+                        localizer.get(slot.name_key()),
+                        item_type.map_or_else(|| localizer.get("inventory.empty_slot"), |item_type| localizer.get(&item_type.name_key()))
+                    ),
+                    InventoryRow::Item(item_type, 1) => localizer.get(&item_type.name_key()),
+                    InventoryRow::Item(item_type, count) => format!("{} x{}", localizer.get(&item_type.name_key()), count),
+                };
+                let background = if index == screen.selected {
+                    Color::srgba(1., 1., 1., 0.15)
+                } else {
+                    Color::NONE
+                };
+                parent
+                    .spawn((
+                        Node {
+                            align_items: AlignItems::Center,
+                            column_gap: Val::Px(6.0),
+                            padding: UiRect::all(Val::Px(2.0)),
+                            ..Default::default()
+                        },
+                        BackgroundColor(background),
+                    ))
+                    .with_children(|row_node| {
+                        let icon_node = Node {
+                            width: Val::Px(24.0),
+                            height: Val::Px(24.0),
+                            ..Default::default()
+                        };
+                        match row.item_type() {
+                            Some(item_type) => row_node.spawn((icons.node(item_type.icon()), icon_node)),
+                            None => row_node.spawn(icon_node),
+                        };
+                        row_node.spawn((
+                            Text::new(label),
+                            TextFont {
+                                font_size: 16.0,
+                                ..Default::default()
+                            },
+                            TextColor(Color::WHITE),
+                        ));
+                    });
+            }
+        });
+    }
+
+    let key_label = |action| {
+        bindings
+            .get(action)
+            .first()
+            .map(InputBinding::label)
+            .unwrap_or_default()
+    };
+    let arg = |name: &str, value: String| (name.to_string(), value);
+
+    let mut lines = Vec::new();
+    if let Some(row) = rows.get(screen.selected) {
+        if let Some(item_type) = row.item_type() {
+            lines.push(localizer.get(&item_type.name_key()));
+            lines.push(localizer.get(&item_type.description_key()));
+            lines.push(String::new());
+        }
+        if let Some(selected) = screen.action {
+            for (index, action) in row.actions().iter().enumerate() {
+                let cursor = if index == selected { ">" } else { " " };
+                lines.push(format!("{} {}", cursor, localizer.get(action.name_key())));
+            }
+            lines.push(String::new());
+        }
+    }
+    if let Some(message) = screen.message {
+        lines.push(localizer.get(message));
+    }
+    lines.push(localizer.format(
+        "inventory.hint",
+        &[arg("confirm", key_label(Action::MenuConfirm)), arg("back", key_label(Action::MenuBack))],
+    ));
+
+    for mut text in widgets.text_query.iter_mut() {
+        text.0 = lines.join("\n");
+    }
+}
+
+pub struct InventoryPlugin;
+
+impl Plugin for InventoryPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<InventoryScreen>()
+            .add_systems(Startup, spawn_inventory_ui)
+            .add_systems(Update, (handle_inventory_input, update_inventory_ui).chain().run_if(in_state(AppState::Playing)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shield_moves_between_inventory_and_off_hand() {
+        let mut inventory = Inventory::default();
+        let mut equipment = Equipment::default();
+        inventory.add(ItemType::SimpleShield, 1);
+        inventory.add(ItemType::Gold, 3);
+
+        assert!(!equipment.equip(&mut inventory, ItemType::Gold));
+        assert!(equipment.equip(&mut inventory, ItemType::SimpleShield));
+        assert!(equipment.can_block());
+        assert_eq!(inventory.count(ItemType::SimpleShield), 0);
+        assert_eq!(
+            InventoryRow::all(&inventory, &equipment),
+            vec![
+                InventoryRow::Slot(EquipSlot::OffHand, Some(ItemType::SimpleShield)),
+                InventoryRow::Item(ItemType::Gold, 3),
+            ]
+        );
+        assert!(InventoryRow::Item(ItemType::Gold, 3).actions().is_empty());
+
+        assert!(equipment.unequip(&mut inventory, EquipSlot::OffHand));
+        assert!(!equipment.can_block());
+        assert_eq!(inventory.count(ItemType::SimpleShield), 1);
+    }
+}
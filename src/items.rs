@@ -2,8 +2,13 @@ use std::str::FromStr;
 
 use bevy::prelude::*;
 use bevy_ecs_ldtk::prelude::*;
+use serde::{Deserialize, Serialize};
 
-use crate::colliders::{spawn_item_sensors, ItemColliderBundle};
+use crate::{
+    app_state::AppState,
+    colliders::{spawn_item_sensors, ItemColliderBundle},
+    inventory::EquipSlot,
+};
 
 #[derive(Debug, Clone, Default, Component, Reflect)]
 pub struct Item {
@@ -12,10 +17,63 @@ pub struct Item {
 }
 
 
-#[derive(Debug, Clone, Default, Component, Reflect)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Component, Reflect, Serialize, Deserialize)]
 pub enum ItemType {
+    /// Also what a bad `type` field falls back to, a stray coin can't break anything
     #[default]
+    Gold,
+    SmallHealingPotion,
     SimpleShield,
+    HealScroll,
+}
+
+impl ItemType {
+    /// The value's name in the LDtk `Item` enum
+    pub fn identifier(&self) -> &'static str {
+        match self {
+            ItemType::Gold => "Gold",
+            ItemType::SmallHealingPotion => "Small_Healing_potion",
+            ItemType::SimpleShield => "Simple_Shield",
+            ItemType::HealScroll => "Heal_Scroll",
+        }
+    }
+
+    /// String table key for the item's display name
+    pub fn name_key(&self) -> String {
+        format!("item.{}", self.identifier())
+    }
+
+    /// Index into `icons_16x16.png`, the same icon the LDtk `Item` enum uses
+    pub fn icon(&self) -> usize {
+        match self {
+            ItemType::Gold => 117,
+            ItemType::SmallHealingPotion => 229,
+            ItemType::SimpleShield => 287,
+            ItemType::HealScroll => 28,
+        }
+    }
+
+    /// String table key for the item's description in the inventory
+    pub fn description_key(&self) -> String {
+        format!("item.{}.description", self.identifier())
+    }
+
+    /// Where the item goes when equipped, `None` for things that can't be worn
+    pub fn equip_slot(&self) -> Option<EquipSlot> {
+        match self {
+            ItemType::SimpleShield => Some(EquipSlot::OffHand),
+            _ => None,
+        }
+    }
+
+    /// Health restored by using the item
+    pub fn heal_amount(&self) -> Option<i32> {
+        match self {
+            ItemType::SmallHealingPotion => Some(25),
+            ItemType::HealScroll => Some(60),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone, Default, Bundle, LdtkEntity)]
@@ -33,8 +91,11 @@ impl FromStr for ItemType {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
 
         match s {
+            "Gold" => Ok(ItemType::Gold),
+            "Small_Healing_potion" => Ok(ItemType::SmallHealingPotion),
             "Simple_Shield" => Ok(ItemType::SimpleShield),
-            _ => Ok(ItemType::SimpleShield),
+            "Heal_Scroll" => Ok(ItemType::HealScroll),
+            _ => Err(format!("unknown item type {}", s)),
         }
     }
     
@@ -43,15 +104,14 @@ impl FromStr for ItemType {
 
 impl Item {
     pub fn from_field(entity_instance: &EntityInstance) -> Item {
-        let item_type_field = entity_instance.get_enum_field("type")
-            .expect("expected entity to have non-nullable type enums field");
-        let item_type = ItemType::from_str(&item_type_field)
-            .unwrap_or(ItemType::SimpleShield);
-        let count_field = entity_instance.get_int_field("count")
-            .expect("expected entity to have non-nullable count int field");
-        let count = *count_field as u32;
+        let item_type = entity_instance.get_enum_field("type")
+            .ok()
+            .and_then(|field| ItemType::from_str(field).ok())
+            .unwrap_or_default();
+        let count = entity_instance.get_int_field("count")
+            .map_or(1, |count| (*count).max(0) as u32);
 
-        println!("Found item: {:?}", item_type);
+        debug!("Found item: {:?}", item_type);
 
         Item {
             item_type,
@@ -61,6 +121,13 @@ impl Item {
     }
 }
 
+/// Sent whenever items end up in the player's inventory, from a pickup or a gift
+#[derive(Event, Debug, Clone)]
+pub struct ItemCollected {
+    pub item_type: ItemType,
+    pub count: u32,
+}
+
 #[derive(Debug, Default, Component, Reflect, Clone)]
 pub struct Loot {
     drops: Vec<ItemType>,
@@ -70,10 +137,10 @@ impl Loot {
     pub fn from_field(entity_instance: &EntityInstance) -> Loot {
         let drops = entity_instance
             .iter_enums_field("loot")
-            .expect("expected entity to have non-nullable loot enums field")
-            .map(|field| ItemType::from_str(field))
-            .collect::<Result<_, _>>()
-            .unwrap();
+            .into_iter()
+            .flatten()
+            .filter_map(|field| ItemType::from_str(field).ok())
+            .collect();
 
             Loot { drops }
     }
@@ -85,7 +152,8 @@ impl Plugin for ItemsPlugin {
     fn build(&self, app: &mut App) {
         app
             .register_type::<Item>()
-            .add_systems(Update, spawn_item_sensors)
+            .add_event::<ItemCollected>()
+            .add_systems(Update, spawn_item_sensors.run_if(in_state(AppState::Playing)))
             .register_ldtk_entity::<ItemBundle>("Item");
     }
 }
\ No newline at end of file
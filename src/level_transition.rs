@@ -0,0 +1,242 @@
+use bevy::{ecs::system::SystemParam, prelude::*, window::PrimaryWindow};
+use bevy_rapier2d::prelude::*;
+
+use crate::{
+    actions::InputLock,
+    app_state::AppState,
+    camera::{camera_follow_player, CameraSettings, FollowCamera, PixelCanvas},
+    game_flow::update_level_selection,
+    player::Player,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TransitionStyle {
+    /// Snap straight to the new level
+    Cut,
+    /// Pan the camera over to the new level
+    #[default]
+    Slide,
+    /// Fade to black and back
+    Fade,
+    /// Close a circle on the player, then open it in the new level
+    Iris,
+}
+
+/// How walking from one level into another looks.
+/// Doors and loading a save have their own fades and don't use this.
+#[derive(Debug, Clone, Resource)]
+pub struct LevelTransitionSettings {
+    pub style: TransitionStyle,
+    pub seconds: f32,
+}
+
+impl Default for LevelTransitionSettings {
+    fn default() -> Self {
+        LevelTransitionSettings {
+            style: TransitionStyle::Slide,
+            seconds: 0.5,
+        }
+    }
+}
+
+/// Sent by `update_level_selection` when the player walks into another level
+#[derive(Event, Debug, Clone)]
+pub struct LevelEntered {
+    pub iid: String,
+}
+
+#[derive(Debug)]
+pub struct ActiveTransition {
+    pub style: TransitionStyle,
+    pub timer: Timer,
+    /// What the camera showed before the level changed
+    pub from_center: Vec2,
+    pub from_view: Vec2,
+}
+
+#[derive(Debug, Default, Resource)]
+pub struct LevelTransition {
+    pub active: Option<ActiveTransition>,
+}
+
+impl LevelTransition {
+    /// The old framing, and how far the camera should be blended from it to the new one
+    pub fn camera_blend(&self) -> Option<(Vec2, Vec2, f32)> {
+        let transition = self.active.as_ref()?;
+        let t = transition.timer.fraction();
+        let blend = match transition.style {
+            TransitionStyle::Cut => 1.,
+            TransitionStyle::Slide => t * t * (3. - 2. * t),
+            // Switch over while the screen is covered
+            TransitionStyle::Fade | TransitionStyle::Iris => {
+                if t < 0.5 {
+                    0.
+                } else {
+                    1.
+                }
+            }
+        };
+        Some((transition.from_center, transition.from_view, blend))
+    }
+
+    /// How much of the screen is covered by the fade or iris, 1 when fully covered
+    fn cover(&self, style: TransitionStyle) -> f32 {
+        match &self.active {
+            Some(transition) if transition.style == style => 1. - (transition.timer.fraction() * 2. - 1.).abs(),
+            _ => 0.,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct TransitionFade;
+
+#[derive(Component)]
+pub struct TransitionIris;
+
+pub fn spawn_transition_overlays(mut commands: Commands) {
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            width: Val::Percent(100.),
+            height: Val::Percent(100.),
+            ..Default::default()
+        },
+        BackgroundColor(Color::NONE),
+        GlobalZIndex(9),
+        TransitionFade,
+    ));
+    // A see-through circle with an outline big enough to cover the rest of the screen
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            ..Default::default()
+        },
+        BorderRadius::MAX,
+        Outline::new(Val::Px(0.), Val::ZERO, Color::BLACK),
+        Visibility::Hidden,
+        GlobalZIndex(9),
+        TransitionIris,
+    ));
+}
+
+pub fn start_level_transition(
+    mut events: EventReader<LevelEntered>,
+    settings: Res<LevelTransitionSettings>,
+    camera_query: Query<&FollowCamera>,
+    mut transition: ResMut<LevelTransition>,
+    mut rapier_config: Query<&mut RapierConfiguration>,
+    mut lock: ResMut<InputLock>,
+) {
+    for event in events.read() {
+        if settings.style == TransitionStyle::Cut {
+            continue;
+        }
+        let Ok(camera) = camera_query.get_single() else { continue };
+        debug!("Transitioning to level {}", event.iid);
+
+        transition.active = Some(ActiveTransition {
+            style: settings.style,
+            timer: Timer::from_seconds(settings.seconds, TimerMode::Once),
+            from_center: camera.shown,
+            from_view: camera.view,
+        });
+        // The player waits at the edge of the new level until the camera is there
+        for mut config in rapier_config.iter_mut() {
+            config.physics_pipeline_active = false;
+        }
+        lock.cutscene = true;
+    }
+}
+
+pub fn run_level_transition(
+    time: Res<Time>,
+    mut transition: ResMut<LevelTransition>,
+    mut rapier_config: Query<&mut RapierConfiguration>,
+    mut lock: ResMut<InputLock>,
+) {
+    let Some(active) = transition.active.as_mut() else { return };
+    active.timer.tick(time.delta());
+    if !active.timer.finished() {
+        return;
+    }
+
+    transition.active = None;
+    for mut config in rapier_config.iter_mut() {
+        config.physics_pipeline_active = true;
+    }
+    lock.cutscene = false;
+}
+
+/// What decides where the world is drawn inside the window
+#[derive(SystemParam)]
+pub struct ScreenArea<'w, 's> {
+    settings: Res<'w, CameraSettings>,
+    canvas: Res<'w, PixelCanvas>,
+    window_query: Query<'w, 's, &'static Window, With<PrimaryWindow>>,
+}
+
+#[allow(clippy::type_complexity)]
+pub fn update_transition_overlays(
+    transition: Res<LevelTransition>,
+    screen_area: ScreenArea,
+    camera_query: Query<(&Transform, &FollowCamera), Without<Player>>,
+    player_query: Query<&Transform, With<Player>>,
+    mut fade_query: Query<&mut BackgroundColor, With<TransitionFade>>,
+    mut iris_query: Query<(&mut Node, &mut Outline, &mut Visibility), With<TransitionIris>>,
+) {
+    for mut color in fade_query.iter_mut() {
+        color.0 = Color::srgba(0., 0., 0., transition.cover(TransitionStyle::Fade));
+    }
+
+    let Ok((mut node, mut outline, mut visibility)) = iris_query.get_single_mut() else { return };
+    let cover = transition.cover(TransitionStyle::Iris);
+    let (Ok(window), Ok((camera_transform, camera)), Ok(player)) =
+        (screen_area.window_query.get_single(), camera_query.get_single(), player_query.get_single())
+    else {
+        return;
+    };
+    if cover == 0. || camera.view == Vec2::ZERO {
+        *visibility = Visibility::Hidden;
+        return;
+    }
+
+    // Where the world ends up on screen, which is only part of the window in pixel-perfect mode
+    let screen = if screen_area.settings.pixel_perfect {
+        (screen_area.canvas.size * screen_area.canvas.scale).as_vec2()
+    } else {
+        window.size()
+    };
+    let screen_min = (window.size() - screen) / 2.;
+    // The camera's translation is the bottom left corner of the view, UI goes top down
+    let fraction = (player.translation.truncate() - camera_transform.translation.truncate()) / camera.view;
+    let center = screen_min + Vec2::new(fraction.x, 1. - fraction.y) * screen;
+
+    let reach = window.size().length();
+    let radius = reach * (1. - cover);
+    node.left = Val::Px(center.x - radius);
+    node.top = Val::Px(center.y - radius);
+    node.width = Val::Px(radius * 2.);
+    node.height = Val::Px(radius * 2.);
+    outline.width = Val::Px(reach * 2.);
+    *visibility = Visibility::Inherited;
+}
+
+pub struct LevelTransitionPlugin;
+
+impl Plugin for LevelTransitionPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_event::<LevelEntered>()
+            .init_resource::<LevelTransitionSettings>()
+            .init_resource::<LevelTransition>()
+            .add_systems(Startup, spawn_transition_overlays)
+            .add_systems(Update, (
+                (start_level_transition, run_level_transition)
+                    .chain()
+                    .after(update_level_selection)
+                    .before(camera_follow_player),
+                update_transition_overlays.after(camera_follow_player),
+            ).run_if(in_state(AppState::Playing)));
+    }
+}
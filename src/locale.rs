@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext},
+    ecs::system::SystemParam,
+    prelude::*,
+};
+use serde::Deserialize;
+
+use crate::actions::Action;
+
+/// Languages with an `assets/locale/<code>.strings.ron` file.
+/// The first one is used for any key another language is missing.
+pub const LANGUAGES: [&str; 2] = ["en", "de"];
+
+#[derive(Debug, Asset, TypePath, Deserialize)]
+pub struct StringTable {
+    /// Name of the language in that language
+    pub language: String,
+    pub strings: HashMap<String, String>,
+}
+
+#[derive(Default)]
+pub struct StringTableLoader;
+
+impl AssetLoader for StringTableLoader {
+    type Asset = StringTable;
+    type Settings = ();
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<StringTable, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        Ok(ron::de::from_bytes(&bytes)?)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["strings.ron"]
+    }
+}
+
+#[derive(Debug, Default, Resource)]
+pub struct Locale {
+    /// Index into [LANGUAGES]
+    pub current: usize,
+    pub tables: Vec<Handle<StringTable>>,
+}
+
+/// Looks up translated strings for the current language
+#[derive(SystemParam)]
+pub struct Localizer<'w> {
+    locale: Res<'w, Locale>,
+    tables: Res<'w, Assets<StringTable>>,
+}
+
+impl Localizer<'_> {
+    fn lookup(&self, key: &str) -> Option<&str> {
+        let table = |index: usize| self.locale.tables.get(index).and_then(|handle| self.tables.get(handle));
+        table(self.locale.current)
+            .and_then(|table| table.strings.get(key))
+            .or_else(|| table(0).and_then(|table| table.strings.get(key)))
+            .map(String::as_str)
+    }
+
+    /// The translation of `key`, or the key itself so missing strings stand out
+    pub fn get(&self, key: &str) -> String {
+        self.lookup(key).unwrap_or(key).to_string()
+    }
+
+    pub fn get_or(&self, key: &str, fallback: &str) -> String {
+        self.lookup(key).unwrap_or(fallback).to_string()
+    }
+
+    /// Like [Localizer::get], filling in `{name}` placeholders from `args`.
+    /// Argument values that are keys themselves get translated too, e.g. item names.
+    pub fn format(&self, key: &str, args: &[(String, String)]) -> String {
+        args.iter().fold(self.get(key), |text, (name, value)| {
+            text.replace(&format!("{{{}}}", name), &self.get_or(value, value))
+        })
+    }
+
+    /// The language was switched or a string table (re)loaded
+    pub fn changed(&self) -> bool {
+        self.locale.is_changed()
+    }
+}
+
+/// Keeps a `Text` in sync with the current language
+#[derive(Debug, Clone, Default, Component)]
+pub struct LocalizedText {
+    pub key: String,
+    pub args: Vec<(String, String)>,
+}
+
+impl LocalizedText {
+    pub fn new(key: impl Into<String>) -> Self {
+        LocalizedText {
+            key: key.into(),
+            args: Vec::new(),
+        }
+    }
+
+    pub fn with_arg(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.args.push((name.into(), value.into()));
+        self
+    }
+}
+
+pub fn load_string_tables(mut locale: ResMut<Locale>, asset_server: Res<AssetServer>) {
+    locale.tables = LANGUAGES
+        .iter()
+        .map(|code| asset_server.load(format!("locale/{}.strings.ron", code)))
+        .collect();
+}
+
+/// Marks the locale changed when a string table finishes loading or is edited,
+/// so text shown before that gets translated
+pub fn refresh_loaded_tables(mut events: EventReader<AssetEvent<StringTable>>, mut locale: ResMut<Locale>) {
+    let refreshed = events
+        .read()
+        .any(|event| matches!(event, AssetEvent::LoadedWithDependencies { .. } | AssetEvent::Modified { .. }));
+    if refreshed {
+        locale.set_changed();
+    }
+}
+
+pub fn switch_language(
+    actions: Res<ButtonInput<Action>>,
+    mut locale: ResMut<Locale>,
+    tables: Res<Assets<StringTable>>,
+) {
+    if actions.just_pressed(Action::SwitchLanguage) {
+        locale.current = (locale.current + 1) % LANGUAGES.len();
+        let language = locale
+            .tables
+            .get(locale.current)
+            .and_then(|handle| tables.get(handle))
+            .map_or(LANGUAGES[locale.current], |table| table.language.as_str());
+        info!("Switched language to {}", language);
+    }
+}
+
+pub fn update_localized_text(
+    localizer: Localizer,
+    mut text_query: Query<(Ref<LocalizedText>, &mut Text)>,
+) {
+    let changed = localizer.changed();
+    for (localized, mut text) in text_query.iter_mut() {
+        if changed || localized.is_changed() {
+            text.0 = localizer.format(&localized.key, &localized.args);
+        }
+    }
+}
+
+pub struct LocalePlugin;
+
+impl Plugin for LocalePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_asset::<StringTable>()
+            .init_asset_loader::<StringTableLoader>()
+            .init_resource::<Locale>()
+            .add_systems(Startup, load_string_tables)
+            .add_systems(Update, (refresh_loaded_tables, switch_language, update_localized_text).chain());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeSet, path::Path};
+
+    use super::*;
+    use crate::dialogue::DialogueGraph;
+
+    fn load_table(code: &str) -> StringTable {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(format!("assets/locale/{}.strings.ron", code));
+        let contents = std::fs::read_to_string(&path).unwrap();
+        ron::from_str(&contents).unwrap_or_else(|err| panic!("{:?} should parse: {}", path, err))
+    }
+
+    #[test]
+    fn every_language_has_every_string() {
+        let fallback = load_table(LANGUAGES[0]);
+        let expected: BTreeSet<_> = fallback.strings.keys().collect();
+        for code in &LANGUAGES[1..] {
+            let table = load_table(code);
+            let keys: BTreeSet<_> = table.strings.keys().collect();
+            assert_eq!(keys, expected, "{} has different keys than {}", code, LANGUAGES[0]);
+        }
+    }
+
+    #[test]
+    fn dialogue_text_is_translated() {
+        let fallback = load_table(LANGUAGES[0]);
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/dialogue");
+        for entry in std::fs::read_dir(dir).unwrap().flatten() {
+            let contents = std::fs::read_to_string(entry.path()).unwrap();
+            let graph: DialogueGraph = ron::from_str(&contents).unwrap();
+            let keys = graph.nodes.values().flat_map(|node| {
+                node.lines
+                    .iter()
+                    .map(|line| &line.text)
+                    .chain(node.choices.iter().map(|choice| &choice.text))
+            });
+            for key in keys {
+                assert!(fallback.strings.contains_key(key), "{:?} uses untranslated {}", entry.path(), key);
+            }
+        }
+    }
+}
@@ -6,7 +6,11 @@ use bevy_ecs_ldtk::prelude::*;
 
 use bevy_rapier2d::prelude::*;
 
+mod app_state;
+mod background;
 mod camera;
+mod camera_effects;
+mod camera_zones;
 mod climbing;
 // /// Bundles for auto-loading Rapier colliders as part of the level
 mod colliders;
@@ -15,65 +19,107 @@ mod npc;
 // /// Handles initialization and switching levels
 mod game_flow;
 mod ground_detection;
-// mod inventory;
+mod inventory;
 // mod misc_objects;
 mod animation;
 mod player;
 mod walls;
 mod state_machine;
 mod dialogue;
+mod doors;
+mod entity_schema;
 mod items;
+mod level_transition;
 mod boss;
 mod health;
+mod hud;
 mod interaction;
 mod abilities;
+mod flags;
+mod actions;
+mod locale;
+mod map;
+mod quests;
+mod shop;
+mod stamina;
+mod replay;
+mod save;
+mod world_state;
+#[cfg(test)]
+mod testing;
 
 fn main() {
-    App::new()
+    let mut app = App::new();
+    app
         .add_plugins(DefaultPlugins.set(ImagePlugin::default_nearest()))
         .add_plugins((
             LdtkPlugin,
             RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0),
         ))
-        .add_plugins(RapierDebugRenderPlugin {
-            // Customize the debug render settings
-            mode: DebugRenderMode::COLLIDER_SHAPES | DebugRenderMode::RIGID_BODY_AXES,
-            style: DebugRenderStyle {
-                rigid_body_axes_length: 20.0,
-                ..default()
-            },
-            ..default()
-        })
-        .insert_resource(LevelSelection::Uid(0))
+        .add_plugins(replay::ReplayPlugin)
+        .insert_resource(game_flow::no_level())
         .insert_resource(LdtkSettings {
             level_spawn_behavior: LevelSpawnBehavior::UseWorldTranslation {
                 load_level_neighbors: true,
             },
             set_clear_color: SetClearColor::FromLevelBackground,
+            // The parallax layers from `background` replace the level background images
+            level_background: LevelBackground::Nonexistent,
             ..Default::default()
         })
+        .add_plugins(app_state::AppStatePlugin)
+        .add_plugins(actions::ActionsPlugin)
+        .add_plugins(camera::CameraPlugin)
+        .add_plugins(camera_effects::CameraEffectsPlugin)
+        .add_plugins(camera_zones::CameraZonesPlugin)
+        .add_plugins(background::BackgroundPlugin)
+        .add_plugins(locale::LocalePlugin)
+        .add_plugins(flags::FlagsPlugin)
+        .add_plugins(world_state::WorldStatePlugin)
         .add_plugins(game_flow::GameFlowPlugin)
+        .add_plugins(entity_schema::EntitySchemaPlugin)
+        .add_plugins(level_transition::LevelTransitionPlugin)
         .add_plugins(walls::WallPlugin)
         .add_plugins(ground_detection::GroundDetectionPlugin)
         .add_plugins(climbing::ClimbingPlugin)
         .add_plugins(player::PlayerPlugin)
+        .add_plugins(dialogue::DialoguePlugin)
         .add_plugins(npc::NPCPlugin)
         .add_plugins(items::ItemsPlugin)
+        .add_plugins(shop::ShopPlugin)
+        .add_plugins(doors::DoorsPlugin)
         .add_plugins(boss::BossesPlugin)
+        .add_plugins(quests::QuestsPlugin)
+        .add_plugins(save::SavePlugin)
+        .add_plugins(map::MapPlugin)
+        .add_plugins(hud::HudPlugin)
+        .add_plugins(inventory::InventoryPlugin)
         // .add_plugins(enemy::EnemyPlugin)
         // .add_systems(Update, inventory::dbg_print_inventory)
         .add_systems(Update, (
-            camera::camera_fit_inside_current_level,
-            (
-                toggle_debug,
-                (|mut debug_render_context: ResMut<DebugRenderContext>| {
-                    debug_render_context.enabled = !debug_render_context.enabled;
-                })
-                .run_if(input_just_pressed(KeyCode::KeyV)),
-            )
-        ))
+            toggle_debug,
+            (|mut debug_render_context: ResMut<DebugRenderContext>| {
+                debug_render_context.enabled = !debug_render_context.enabled;
+            })
+            .run_if(input_just_pressed(actions::Action::ToggleDebug))
+            .run_if(resource_exists::<DebugRenderContext>),
+        ));
         // .add_plugins(misc_objects::MiscObjectsPlugin)
-        .run();
+
+    // Only in debug builds, starts hidden until ToggleDebug
+    #[cfg(debug_assertions)]
+    app.add_plugins(RapierDebugRenderPlugin {
+        enabled: false,
+        // Customize the debug render settings
+        mode: DebugRenderMode::COLLIDER_SHAPES | DebugRenderMode::RIGID_BODY_AXES,
+        style: DebugRenderStyle {
+            rigid_body_axes_length: 20.0,
+            ..default()
+        },
+        ..default()
+    });
+
+    app.run();
 }
 
 #[derive(Component)]
@@ -0,0 +1,347 @@
+use std::collections::BTreeSet;
+
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+};
+use bevy_ecs_ldtk::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    actions::{Action, InputLock, Menu},
+    app_state::AppState,
+    game_flow::LdtkProjects,
+    player::Player,
+};
+
+const MINIMAP_SIZE: Vec2 = Vec2::new(180., 110.);
+/// UI pixels per world pixel
+const MINIMAP_SCALE: f32 = 0.12;
+const MAP_SCALE: f32 = 0.4;
+const MARKER_SIZE: f32 = 5.;
+
+const EMPTY_COLOR: [u8; 4] = [40, 44, 60, 200];
+const WALL_COLOR: [u8; 4] = [190, 190, 200, 255];
+
+/// LDtk entities that get a marker on the map, by identifier
+const MARKERS: [(&str, Color); 2] = [
+    ("Door", Color::srgb(0.85, 0.6, 0.3)),
+    ("Shop", Color::srgb(1., 0.85, 0.2)),
+];
+const PLAYER_MARKER_COLOR: Color = Color::srgb(0.3, 1., 0.4);
+
+/// Iids of the levels the player has been in. Only these are drawn on the map.
+#[derive(Debug, Clone, Default, Resource, Serialize, Deserialize)]
+pub struct ExploredLevels {
+    pub levels: BTreeSet<String>,
+}
+
+#[derive(Debug)]
+pub struct MapLevel {
+    pub iid: String,
+    /// In LDtk world pixels, y pointing down like UI
+    pub rect: Rect,
+    pub image: Handle<Image>,
+    pub markers: Vec<(Vec2, Color)>,
+}
+
+/// Every level's outline and collisions, built once from the LDtk project
+#[derive(Debug, Default, Resource)]
+pub struct WorldMap {
+    pub levels: Vec<MapLevel>,
+}
+
+#[derive(Debug, Default, Resource)]
+pub struct MapScreen {
+    pub open: bool,
+}
+
+/// Holds the levels of a map, moved around so the player stays in the middle
+#[derive(Component)]
+pub struct MapView {
+    pub scale: f32,
+}
+
+/// A level's image or one of its markers, hidden until the level is explored
+#[derive(Component)]
+pub struct MapLevelNode {
+    pub iid: String,
+}
+
+#[derive(Component)]
+pub struct FullMapUI;
+
+/// One pixel per IntGrid cell, anything solid drawn as wall
+pub fn collision_pixels(int_grid_csv: &[i32]) -> Vec<u8> {
+    int_grid_csv
+        .iter()
+        .flat_map(|value| match value {
+            0 => EMPTY_COLOR,
+            _ => WALL_COLOR,
+        })
+        .collect()
+}
+
+fn map_level(level: &Level, images: &mut Assets<Image>) -> MapLevel {
+    let layers = level.layer_instances.iter().flatten();
+    let image = layers
+        .clone()
+        .find(|layer| layer.identifier == "Collisions")
+        .map(|layer| {
+            Image::new(
+                Extent3d {
+                    width: layer.c_wid as u32,
+                    height: layer.c_hei as u32,
+                    depth_or_array_layers: 1,
+                },
+                TextureDimension::D2,
+                collision_pixels(&layer.int_grid_csv),
+                TextureFormat::Rgba8UnormSrgb,
+                RenderAssetUsages::RENDER_WORLD,
+            )
+        })
+        .map(|image| images.add(image))
+        .unwrap_or_default();
+
+    let markers = layers
+        .flat_map(|layer| &layer.entity_instances)
+        .filter_map(|entity| {
+            let (_, color) = MARKERS.iter().find(|(identifier, _)| *identifier == entity.identifier)?;
+            // Centre of the entity, whatever its pivot
+            let px = entity.px.as_vec2() + (Vec2::splat(0.5) - entity.pivot) * Vec2::new(entity.width as f32, entity.height as f32);
+            Some((Vec2::new(level.world_x as f32, level.world_y as f32) + px, *color))
+        })
+        .collect();
+
+    MapLevel {
+        iid: level.iid.clone(),
+        rect: Rect::new(
+            level.world_x as f32,
+            level.world_y as f32,
+            (level.world_x + level.px_wid) as f32,
+            (level.world_y + level.px_hei) as f32,
+        ),
+        image,
+        markers,
+    }
+}
+
+pub fn build_world_map(
+    mut world_map: ResMut<WorldMap>,
+    ldtk_projects: LdtkProjects,
+    mut images: ResMut<Assets<Image>>,
+) {
+    if !world_map.levels.is_empty() {
+        return;
+    }
+    world_map.levels = ldtk_projects.levels().map(|level| map_level(level, &mut images)).collect();
+}
+
+pub fn mark_explored(
+    mut explored: ResMut<ExploredLevels>,
+    level_selection: Res<LevelSelection>,
+    ldtk_projects: LdtkProjects,
+) {
+    if let Some(level) = ldtk_projects.selected_level(&level_selection) {
+        if !explored.levels.contains(&level.iid) {
+            info!("Explored {}", level.identifier);
+            explored.levels.insert(level.iid.clone());
+        }
+    }
+}
+
+/// A clipped box with a [MapView] anchored in its middle and the player marker on top
+fn spawn_map(parent: &mut ChildBuilder, scale: f32) {
+    parent
+        .spawn(Node {
+            position_type: PositionType::Absolute,
+            left: Val::Percent(50.),
+            top: Val::Percent(50.),
+            ..Default::default()
+        })
+        .with_children(|anchor| {
+            anchor.spawn((
+                Node {
+                    position_type: PositionType::Absolute,
+                    ..Default::default()
+                },
+                MapView { scale },
+            ));
+            anchor.spawn((
+                Node {
+                    position_type: PositionType::Absolute,
+                    left: Val::Px(-MARKER_SIZE / 2.),
+                    top: Val::Px(-MARKER_SIZE / 2.),
+                    width: Val::Px(MARKER_SIZE),
+                    height: Val::Px(MARKER_SIZE),
+                    ..Default::default()
+                },
+                BackgroundColor(PLAYER_MARKER_COLOR),
+            ));
+        });
+}
+
+pub fn spawn_map_ui(mut commands: Commands) {
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                // Below the HUD
+                top: Val::Px(110.0),
+                left: Val::Px(20.0),
+                width: Val::Px(MINIMAP_SIZE.x),
+                height: Val::Px(MINIMAP_SIZE.y),
+                border: UiRect::all(Val::Px(2.0)),
+                overflow: Overflow::clip(),
+                ..Default::default()
+            },
+            BackgroundColor::from(Color::srgba(0.1, 0.1, 0.1, 0.7)),
+            BorderColor(Color::srgb(0.6, 0.6, 0.6)),
+        ))
+        .with_children(|parent| spawn_map(parent, MINIMAP_SCALE));
+
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.),
+                height: Val::Percent(100.),
+                overflow: Overflow::clip(),
+                ..Default::default()
+            },
+            Visibility::Hidden,
+            BackgroundColor::from(Color::srgba(0.05, 0.05, 0.08, 0.95)),
+            GlobalZIndex(5),
+            FullMapUI,
+        ))
+        .with_children(|parent| spawn_map(parent, MAP_SCALE));
+}
+
+/// Fills each map view with the levels once the world map is built
+pub fn populate_map_views(
+    mut commands: Commands,
+    world_map: Res<WorldMap>,
+    view_query: Query<(Entity, &MapView), Without<Children>>,
+) {
+    if world_map.levels.is_empty() {
+        return;
+    }
+    for (entity, view) in view_query.iter() {
+        commands.entity(entity).with_children(|parent| {
+            for level in world_map.levels.iter() {
+                let rect = Rect::from_corners(level.rect.min * view.scale, level.rect.max * view.scale);
+                parent.spawn((
+                    ImageNode::new(level.image.clone()),
+                    Node {
+                        position_type: PositionType::Absolute,
+                        left: Val::Px(rect.min.x),
+                        top: Val::Px(rect.min.y),
+                        width: Val::Px(rect.width()),
+                        height: Val::Px(rect.height()),
+                        ..Default::default()
+                    },
+                    Visibility::Hidden,
+                    MapLevelNode { iid: level.iid.clone() },
+                ));
+                for (position, color) in level.markers.iter() {
+                    let position = *position * view.scale;
+                    parent.spawn((
+                        Node {
+                            position_type: PositionType::Absolute,
+                            left: Val::Px(position.x - MARKER_SIZE / 2.),
+                            top: Val::Px(position.y - MARKER_SIZE / 2.),
+                            width: Val::Px(MARKER_SIZE),
+                            height: Val::Px(MARKER_SIZE),
+                            ..Default::default()
+                        },
+                        BackgroundColor(*color),
+                        Visibility::Hidden,
+                        MapLevelNode { iid: level.iid.clone() },
+                    ));
+                }
+            }
+        });
+    }
+}
+
+pub fn update_map_views(
+    explored: Res<ExploredLevels>,
+    player_query: Query<&Transform, With<Player>>,
+    mut view_query: Query<(&MapView, &mut Node)>,
+    mut level_node_query: Query<(&MapLevelNode, &mut Visibility)>,
+) {
+    for (node, mut visibility) in level_node_query.iter_mut() {
+        let shown = if explored.levels.contains(&node.iid) {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+        visibility.set_if_neq(shown);
+    }
+
+    let Ok(player_transform) = player_query.get_single() else { return };
+    // LDtk's y axis points down, like the UI's
+    let player = Vec2::new(player_transform.translation.x, -player_transform.translation.y);
+    for (view, mut node) in view_query.iter_mut() {
+        node.left = Val::Px(-player.x * view.scale);
+        node.top = Val::Px(-player.y * view.scale);
+    }
+}
+
+pub fn toggle_map_screen(
+    actions: Res<ButtonInput<Action>>,
+    mut map_screen: ResMut<MapScreen>,
+    mut lock: ResMut<InputLock>,
+    mut ui_query: Query<&mut Visibility, With<FullMapUI>>,
+) {
+    let toggle = actions.just_pressed(Action::ToggleMap) || (map_screen.open && actions.just_pressed(Action::MenuBack));
+    if !toggle {
+        return;
+    }
+    if map_screen.open {
+        lock.close_menu(Menu::Map);
+    } else if !lock.open_menu(Menu::Map) {
+        // Another menu has the controls
+        return;
+    }
+    map_screen.open = !map_screen.open;
+    for mut visibility in ui_query.iter_mut() {
+        *visibility = if map_screen.open { Visibility::Visible } else { Visibility::Hidden };
+    }
+}
+
+pub struct MapPlugin;
+
+impl Plugin for MapPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<ExploredLevels>()
+            .init_resource::<WorldMap>()
+            .init_resource::<MapScreen>()
+            .add_systems(Startup, spawn_map_ui)
+            .add_systems(Update, (
+                (build_world_map, populate_map_views).chain(),
+                mark_explored,
+                update_map_views,
+                toggle_map_screen,
+            ).run_if(in_state(AppState::Playing)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collisions_become_one_pixel_per_cell() {
+        let pixels = collision_pixels(&[0, 1, 0, 3]);
+        assert_eq!(pixels.len(), 16);
+        assert_eq!(pixels[0..4], EMPTY_COLOR);
+        assert_eq!(pixels[4..8], WALL_COLOR);
+        assert_eq!(pixels[8..12], EMPTY_COLOR);
+        assert_eq!(pixels[12..16], WALL_COLOR);
+    }
+}
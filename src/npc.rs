@@ -2,7 +2,7 @@ use bevy::prelude::*;
 use bevy_ecs_ldtk::prelude::*;
 use bevy_rapier2d::prelude::*;
 
-use crate::{colliders::{spawn_npc_sensors, ColliderBundle, NPCColliderBundle}, dialogue::{spawn_dialogue_ui, update_dialogue_ui, Dialogue, DialogueUI}, player::{self, Player}};
+use crate::{app_state::AppState, colliders::{spawn_npc_sensors, ColliderBundle, NPCColliderBundle}, dialogue::{end_dialogue, Dialogue, DialogueEnded, DialogueRunner}, player::{self, Player}};
 
 #[derive(Default, Component)]
 pub struct NPC;
@@ -23,41 +23,33 @@ pub struct NPCBundle {
     global_transform: GlobalTransform,
 }
 
-fn name_from_field(entity_instance: &EntityInstance) -> Name {
+pub fn name_from_field(entity_instance: &EntityInstance) -> Name {
     Name::new(
         entity_instance
             .get_string_field("name")
-            .expect("expected entity to have non-nullable name string field")
-            .clone(),
+            .cloned()
+            .unwrap_or_else(|_| entity_instance.identifier.clone()),
     )
 }
 
-// Update the NPC dialogue trigger system
+// Conversations are started from the interaction prompt,
+// walking away from the NPC ends them
 pub fn handle_npc_dialogue_trigger(
     mut collision_events: EventReader<CollisionEvent>,
-    npc_query: Query<(&Dialogue, &Name), With<NPC>>,
+    npc_query: Query<(), With<NPC>>,
     player_query: Query<Entity, With<Player>>,
     sensor_query: Query<&Parent, With<Sensor>>,
-    mut dialogue_ui_query: Query<&mut DialogueUI>,
+    mut runner: ResMut<DialogueRunner>,
+    mut ended: EventWriter<DialogueEnded>,
 ) {
     for collision_event in collision_events.read() {
-        match collision_event {
-            CollisionEvent::Started(e1, e2, _) => {
-                let sensor_parent = get_sensor_parent(*e1, *e2, &sensor_query);
-                let is_player = is_player_involved(*e1, *e2, &player_query);
+        if let CollisionEvent::Stopped(e1, e2, _) = collision_event {
+            let sensor_parent = get_sensor_parent(*e1, *e2, &sensor_query);
+            let is_player = is_player_involved(*e1, *e2, &player_query);
 
-                if let (Some(npc_entity), true) = (sensor_parent, is_player) {
-                    if let Ok((dialogue, name)) = npc_query.get(npc_entity) {
-                        if let Ok(mut dialogue_ui) = dialogue_ui_query.get_single_mut() {
-                            dialogue_ui.active = true;
-                            dialogue_ui.text = format!("{}: {}", name, dialogue.text);
-                        }
-                    }
-                }
-            }
-            CollisionEvent::Stopped(_, _, _) => {
-                if let Ok(mut dialogue_ui) = dialogue_ui_query.get_single_mut() {
-                    dialogue_ui.active = false;
+            if let (Some(npc_entity), true) = (sensor_parent, is_player) {
+                if npc_query.contains(npc_entity) {
+                    end_dialogue(&mut runner, &mut ended);
                 }
             }
         }
@@ -81,7 +73,7 @@ pub fn debug_npc_setup(
     npc_query: Query<(Entity, &Dialogue, &Name), With<NPC>>,
 ) {
     for (entity, dialogue, name) in npc_query.iter() {
-        println!("NPC found: {:?} named {} with dialogue: {}", entity, name, dialogue.text);
+        println!("NPC found: {:?} named {} with dialogue: {}", entity, name, dialogue.id);
     }
 }
 
@@ -90,13 +82,11 @@ pub struct NPCPlugin;
 impl Plugin for NPCPlugin {
     fn build(&self, app: &mut App) {
         app
-            .add_systems(Startup, spawn_dialogue_ui)
             .add_systems(Update, (
                 // debug_npc_setup,
                 spawn_npc_sensors,
                 handle_npc_dialogue_trigger,
-                update_dialogue_ui,
-            ))
+            ).run_if(in_state(AppState::Playing)))
             .register_ldtk_entity::<NPCBundle>("NPC");
     }
 }
\ No newline at end of file
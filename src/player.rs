@@ -2,11 +2,14 @@ use bevy::prelude::*;
 use bevy_ecs_ldtk::prelude::*;
 use bevy_rapier2d::prelude::*;
 
-use crate::abilities::Abilities;
+use crate::actions::Action;
+use crate::app_state::AppState;
 use crate::animation::{animate_player_sprite, PlayerAnimation};
-// use crate::{climbing::Climber, inventory::Inventory};
 use crate::climbing::Climber;
+use crate::inventory::{Equipment, Inventory};
+use crate::npc::name_from_field;
 use crate::health::Health;
+use crate::stamina::{update_stamina, Stamina};
 use crate::state_machine::{player_state_transition, AttackType, Direction, MovementType, PlayerEvent, PlayerState};
 use crate::{colliders::ColliderBundle, ground_detection::GroundDetection};
 
@@ -20,6 +23,8 @@ pub struct PlayerBundle {
     #[from_entity_instance]
     pub collider_bundle: ColliderBundle,
     pub player: Player,
+    #[with(name_from_field)]
+    pub name: Name,
     #[worldly]
     pub worldly: Worldly,
     pub climber: Climber,
@@ -27,9 +32,8 @@ pub struct PlayerBundle {
     // Add Transform and GlobalTransform
     // pub transform: Transform,
 
-    // Build Items Component manually by using `impl From<&EntityInstance>`
-    // #[from_entity_instance]
-    // items: Inventory,
+    #[with(Inventory::from_field)]
+    pub inventory: Inventory,
 
     // The whole EntityInstance can be stored directly as an EntityInstance component
     #[from_entity_instance]
@@ -37,24 +41,25 @@ pub struct PlayerBundle {
     animation: PlayerAnimation,
     #[with(Health::from_field)]
     pub health: Health,
-    abilities: Abilities,
+    pub stamina: Stamina,
+    pub equipment: Equipment,
 
 }
 
 pub fn handle_player_movement_and_input(
-    mut query: Query<(&mut Velocity, &GroundDetection, &mut Climber, &Abilities), With<Player>>,
+    mut query: Query<(&mut Velocity, &GroundDetection, &mut Climber, &Equipment, &Stamina), With<Player>>,
     mut player_events: EventWriter<PlayerEvent>,
-    input: Res<ButtonInput<KeyCode>>,
+    actions: Res<ButtonInput<Action>>,
     time: Res<Time>,
 ) {
-    let Ok((mut velocity, ground_detection, mut climber, abilities)) = query.get_single_mut() else { return };
+    let Ok((mut velocity, ground_detection, mut climber, equipment, stamina)) = query.get_single_mut() else { return };
     
     // Handle horizontal movement
     let mut direction = 0.0;
-    if input.pressed(KeyCode::ArrowLeft) {
+    if actions.pressed(Action::MoveLeft) {
         direction -= 1.0;
     }
-    if input.pressed(KeyCode::ArrowRight) {
+    if actions.pressed(Action::MoveRight) {
         direction += 1.0;
     }
 
@@ -70,17 +75,17 @@ pub fn handle_player_movement_and_input(
     }
 
     // Handle blocking
-    if abilities.can_block() {
-        if input.just_pressed(KeyCode::KeyD) {
+    if equipment.can_block() {
+        if actions.just_pressed(Action::Block) && stamina.can_block() {
             player_events.send(PlayerEvent::BlockStarted);
         }
-        if input.just_released(KeyCode::KeyD) {
+        if actions.just_released(Action::Block) {
             player_events.send(PlayerEvent::BlockEnded);
         }
     }
 
     // Handle jumping
-    if (input.just_pressed(KeyCode::Space) || input.just_pressed(KeyCode::ArrowUp)) 
+    if actions.just_pressed(Action::Jump)
         && ground_detection.on_ground 
         && !climber.climbing 
     {
@@ -95,7 +100,7 @@ pub fn handle_player_movement_and_input(
     }
 
     // Handle attack input
-    if input.just_pressed(KeyCode::KeyA) {
+    if actions.just_pressed(Action::Attack) {
         player_events.send(PlayerEvent::AttackStarted(AttackType::Melee));
     }
 }
@@ -166,10 +171,11 @@ impl Plugin for PlayerPlugin {
             .add_systems(Update, (
                 handle_player_children,
                handle_player_movement_and_input,
+               update_stamina,
                player_state_transition,
                handle_sprite_direction,
                animate_player_sprite
-            ).chain())
+            ).chain().run_if(in_state(AppState::Playing)))
             .register_ldtk_entity::<PlayerBundle>("Player");
     }
 }
@@ -0,0 +1,418 @@
+use std::collections::{BTreeMap, HashMap};
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    actions::Action,
+    app_state::AppState,
+    boss::EnemyDefeated,
+    dialogue::{DialogueEffect, StartDialogue},
+    flags::GameFlags,
+    inventory::{Equipment, Inventory},
+    items::{ItemCollected, ItemType},
+    locale::Localizer,
+    player::Player,
+};
+
+const QUEST_BOOK_PATH: &str = "quests/main.quests.ron";
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub enum Objective {
+    Collect(ItemType, u32),
+    /// Matches the `Name` of the defeated enemy
+    Defeat(String),
+    /// Starting a conversation with the named speaker
+    TalkTo(String),
+}
+
+impl Objective {
+    pub fn target(&self) -> u32 {
+        match self {
+            Objective::Collect(_, count) => *count,
+            Objective::Defeat(_) | Objective::TalkTo(_) => 1,
+        }
+    }
+
+    /// Progress from before the quest was given. Worn items count as held.
+    pub fn progress_before_start(&self, held: Option<(&Inventory, &Equipment)>, flags: &GameFlags) -> u32 {
+        match self {
+            Objective::Collect(item_type, count) => held
+                .map_or(0, |(inventory, equipment)| inventory.count(*item_type) + equipment.count(*item_type))
+                .min(*count),
+            Objective::Defeat(name) => flags.get_bool(&format!("defeated:{}", name.to_lowercase())) as u32,
+            Objective::TalkTo(_) => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ObjectiveDef {
+    pub kind: Objective,
+    /// Key into the string tables in `assets/locale`
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct QuestDef {
+    /// Key into the string tables in `assets/locale`
+    pub title: String,
+    pub objectives: Vec<ObjectiveDef>,
+    /// Applied like dialogue effects once the quest is handed in
+    #[serde(default)]
+    pub rewards: Vec<DialogueEffect>,
+}
+
+/// Every quest in the game, loaded from `assets/quests/main.quests.ron`
+#[derive(Debug, Asset, TypePath, Deserialize)]
+pub struct QuestBook {
+    pub quests: HashMap<String, QuestDef>,
+}
+
+#[derive(Default)]
+pub struct QuestBookLoader;
+
+impl AssetLoader for QuestBookLoader {
+    type Asset = QuestBook;
+    type Settings = ();
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<QuestBook, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        Ok(ron::de::from_bytes(&bytes)?)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["quests.ron"]
+    }
+}
+
+#[derive(Debug, Default, Resource)]
+pub struct QuestBookHandle(pub Handle<QuestBook>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum QuestStatus {
+    Active,
+    /// Every objective is done, waiting to be handed in through dialogue
+    Ready,
+    Completed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuestProgress {
+    pub status: QuestStatus,
+    /// Progress towards each objective's target, in the quest's order
+    pub objectives: Vec<u32>,
+}
+
+/// Quests the player has started. Mirrored into [GameFlags] as
+/// `quest_started:<id>`, `quest_ready:<id>` and `quest_completed:<id>`
+/// so dialogue can branch on them.
+#[derive(Debug, Clone, Default, Resource, Serialize, Deserialize)]
+pub struct QuestLog {
+    pub quests: BTreeMap<String, QuestProgress>,
+}
+
+impl QuestLog {
+    fn advance(&mut self, book: &QuestBook, mut amount: impl FnMut(&Objective) -> u32) {
+        for (id, progress) in self.quests.iter_mut() {
+            if progress.status != QuestStatus::Active {
+                continue;
+            }
+            let Some(quest) = book.quests.get(id) else { continue };
+            for (objective, done) in quest.objectives.iter().zip(progress.objectives.iter_mut()) {
+                *done = (*done + amount(&objective.kind)).min(objective.kind.target());
+            }
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct QuestLogUI;
+
+#[derive(Component)]
+pub struct QuestLogText;
+
+pub fn load_quest_book(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(QuestBookHandle(asset_server.load(QUEST_BOOK_PATH)));
+}
+
+pub fn track_objectives(
+    mut collected: EventReader<ItemCollected>,
+    mut defeated: EventReader<EnemyDefeated>,
+    mut dialogues: EventReader<StartDialogue>,
+    mut log: ResMut<QuestLog>,
+    book_handle: Res<QuestBookHandle>,
+    books: Res<Assets<QuestBook>>,
+) {
+    let Some(book) = books.get(&book_handle.0) else { return };
+
+    for event in collected.read() {
+        log.advance(book, |objective| match objective {
+            Objective::Collect(item_type, _) if *item_type == event.item_type => event.count,
+            _ => 0,
+        });
+    }
+    for event in defeated.read() {
+        log.advance(book, |objective| match objective {
+            Objective::Defeat(name) if *name == event.name => 1,
+            _ => 0,
+        });
+    }
+    for event in dialogues.read() {
+        log.advance(book, |objective| match objective {
+            Objective::TalkTo(name) if *name == event.speaker => 1,
+            _ => 0,
+        });
+    }
+}
+
+/// Starts and hands in quests from dialogue effects. Runs after [track_objectives],
+/// so the conversation that starts a quest doesn't also count towards it.
+pub fn apply_quest_effects(
+    mut effects: ParamSet<(EventReader<DialogueEffect>, EventWriter<DialogueEffect>)>,
+    mut log: ResMut<QuestLog>,
+    mut flags: ResMut<GameFlags>,
+    book_handle: Res<QuestBookHandle>,
+    books: Res<Assets<QuestBook>>,
+    player_query: Query<(&Inventory, &Equipment), With<Player>>,
+) {
+    let Some(book) = books.get(&book_handle.0) else { return };
+    let received: Vec<DialogueEffect> = effects.p0().read().cloned().collect();
+    let mut reward_effects = Vec::new();
+
+    for effect in &received {
+        match effect {
+            DialogueEffect::StartQuest(id) => {
+                let Some(quest) = book.quests.get(id) else {
+                    warn!("Quest {} doesn't exist", id);
+                    continue;
+                };
+                if log.quests.contains_key(id) {
+                    continue;
+                }
+
+                // Things done before the quest was given still count
+                let held = player_query.get_single().ok();
+                let objectives = quest
+                    .objectives
+                    .iter()
+                    .map(|objective| objective.kind.progress_before_start(held, &flags))
+                    .collect();
+
+                log.quests.insert(id.clone(), QuestProgress {
+                    status: QuestStatus::Active,
+                    objectives,
+                });
+                flags.set_bool(format!("quest_started:{}", id), true);
+                info!("Started quest {}", id);
+            }
+            DialogueEffect::CompleteQuest(id) => {
+                let Some(progress) = log.quests.get_mut(id) else { continue };
+                if progress.status != QuestStatus::Ready {
+                    warn!("Quest {} was handed in before it was ready", id);
+                    continue;
+                }
+
+                progress.status = QuestStatus::Completed;
+                flags.set_bool(format!("quest_ready:{}", id), false);
+                flags.set_bool(format!("quest_completed:{}", id), true);
+                if let Some(quest) = book.quests.get(id) {
+                    reward_effects.extend(quest.rewards.iter().cloned());
+                }
+                info!("Completed quest {}", id);
+            }
+            _ => (),
+        }
+    }
+
+    // Rewards go through the same effects as dialogue, e.g. `GiveItem`
+    effects.p1().send_batch(reward_effects);
+}
+
+/// Marks quests ready to hand in once all their objectives are done
+pub fn update_quest_status(
+    mut log: ResMut<QuestLog>,
+    mut flags: ResMut<GameFlags>,
+    book_handle: Res<QuestBookHandle>,
+    books: Res<Assets<QuestBook>>,
+) {
+    if !log.is_changed() {
+        return;
+    }
+    let Some(book) = books.get(&book_handle.0) else { return };
+
+    for (id, progress) in log.quests.iter_mut() {
+        let Some(quest) = book.quests.get(id) else { continue };
+        let done = quest
+            .objectives
+            .iter()
+            .zip(&progress.objectives)
+            .all(|(objective, done)| *done >= objective.kind.target());
+
+        if progress.status == QuestStatus::Active && done {
+            progress.status = QuestStatus::Ready;
+            flags.set_bool(format!("quest_ready:{}", id), true);
+            info!("Quest {} is ready to hand in", id);
+        }
+    }
+}
+
+pub fn spawn_quest_log_ui(mut commands: Commands) {
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(20.0),
+                right: Val::Px(20.0),
+                max_width: Val::Px(320.0),
+                padding: UiRect::all(Val::Px(10.0)),
+                ..Default::default()
+            },
+            Visibility::Hidden,
+            BackgroundColor::from(Color::srgba(0.2, 0.2, 0.2, 0.8)),
+            QuestLogUI,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new(""),
+                TextFont {
+                    font_size: 16.0,
+                    ..Default::default()
+                },
+                TextColor(Color::WHITE),
+                QuestLogText,
+            ));
+        });
+}
+
+pub fn toggle_quest_log(
+    actions: Res<ButtonInput<Action>>,
+    mut ui_query: Query<&mut Visibility, With<QuestLogUI>>,
+) {
+    if !actions.just_pressed(Action::ToggleQuestLog) {
+        return;
+    }
+    for mut visibility in ui_query.iter_mut() {
+        *visibility = match *visibility {
+            Visibility::Hidden => Visibility::Visible,
+            _ => Visibility::Hidden,
+        };
+    }
+}
+
+pub fn update_quest_log_ui(
+    log: Res<QuestLog>,
+    book_handle: Res<QuestBookHandle>,
+    books: Res<Assets<QuestBook>>,
+    localizer: Localizer,
+    mut text_query: Query<&mut Text, With<QuestLogText>>,
+) {
+    if !log.is_changed() && !localizer.changed() {
+        return;
+    }
+    let Some(book) = books.get(&book_handle.0) else { return };
+
+    let mut lines = vec![localizer.get("quest_log.title")];
+    if log.quests.is_empty() {
+        lines.push(localizer.get("quest_log.empty"));
+    }
+    // Quests still in progress first
+    let mut quests: Vec<_> = log.quests.iter().collect();
+    quests.sort_by_key(|(_, progress)| progress.status == QuestStatus::Completed);
+
+    for (id, progress) in quests {
+        let Some(quest) = book.quests.get(id) else { continue };
+        let title = localizer.get(&quest.title);
+        lines.push(String::new());
+        match progress.status {
+            QuestStatus::Active => lines.push(title),
+            QuestStatus::Ready => lines.push(format!("{} - {}", title, localizer.get("quest_log.ready"))),
+            QuestStatus::Completed => {
+                lines.push(format!("{} - {}", title, localizer.get("quest_log.completed")));
+                continue;
+            }
+        }
+        for (objective, done) in quest.objectives.iter().zip(&progress.objectives) {
+            let target = objective.kind.target();
+            let check = if *done >= target { "x" } else { " " };
+            let mut line = format!("[{}] {}", check, localizer.get(&objective.text));
+            if target > 1 {
+                line.push_str(&format!(" ({}/{})", done, target));
+            }
+            lines.push(line);
+        }
+    }
+
+    for mut text in text_query.iter_mut() {
+        text.0 = lines.join("\n");
+    }
+}
+
+pub struct QuestsPlugin;
+
+impl Plugin for QuestsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_asset::<QuestBook>()
+            .init_asset_loader::<QuestBookLoader>()
+            .init_resource::<QuestBookHandle>()
+            .init_resource::<QuestLog>()
+            .add_systems(Startup, (load_quest_book, spawn_quest_log_ui))
+            .add_systems(Update, (
+                track_objectives,
+                apply_quest_effects,
+                update_quest_status,
+                toggle_quest_log,
+                update_quest_log_ui,
+            ).chain().run_if(in_state(AppState::Playing)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn bundled_quests_are_translated() {
+        let assets = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+        let contents = std::fs::read_to_string(assets.join(QUEST_BOOK_PATH)).unwrap();
+        let book: QuestBook = ron::from_str(&contents).unwrap_or_else(|err| panic!("quests should parse: {}", err));
+        let contents = std::fs::read_to_string(assets.join("locale/en.strings.ron")).unwrap();
+        let strings: crate::locale::StringTable = ron::from_str(&contents).unwrap();
+
+        for (id, quest) in &book.quests {
+            assert!(!quest.objectives.is_empty(), "quest {} has no objectives", id);
+            let keys = std::iter::once(&quest.title).chain(quest.objectives.iter().map(|objective| &objective.text));
+            for key in keys {
+                assert!(strings.strings.contains_key(key), "quest {} uses untranslated {}", id, key);
+            }
+        }
+    }
+
+    #[test]
+    fn worn_items_count_when_a_quest_starts() {
+        // Picking up the shield equips it straight away, leaving none in the inventory
+        let mut inventory = Inventory::default();
+        let mut equipment = Equipment::default();
+        inventory.add(ItemType::SimpleShield, 1);
+        assert!(equipment.equip(&mut inventory, ItemType::SimpleShield));
+        assert_eq!(inventory.count(ItemType::SimpleShield), 0);
+
+        let objective = Objective::Collect(ItemType::SimpleShield, 1);
+        let flags = GameFlags::default();
+        assert_eq!(objective.progress_before_start(Some((&inventory, &equipment)), &flags), 1);
+        assert_eq!(objective.progress_before_start(None, &flags), 0);
+    }
+}
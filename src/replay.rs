@@ -0,0 +1,290 @@
+use std::{fs, path::PathBuf, time::Duration};
+
+use bevy::{prelude::*, time::TimeUpdateStrategy};
+use bevy_rapier2d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    actions::{update_action_state, Action},
+    app_state::AppState,
+    player::Player,
+};
+
+/// Every recorded frame advances time and physics by exactly this much,
+/// so a replay steps rapier identically to the run that produced it
+pub const FRAME_DT: f32 = 1. / 60.;
+
+/// Player position is stored every this many frames to check a replay against
+const TRAJECTORY_INTERVAL: usize = 10;
+
+/// How far (in pixels) a replayed player may stray before the replay counts as diverged
+const TRAJECTORY_TOLERANCE: f32 = 0.01;
+
+const RECORDING_VERSION: u32 = 1;
+
+/// Per-frame action state captured from the first frame the player exists
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Recording {
+    pub version: u32,
+    pub frame_dt: f32,
+    /// Runs of identical action state: (number of frames, pressed actions)
+    pub frames: Vec<(u32, Vec<Action>)>,
+    /// Player position sampled every `TRAJECTORY_INTERVAL` frames
+    pub trajectory: Vec<Vec2>,
+}
+
+impl Recording {
+    /// An empty recording at the fixed [FRAME_DT]
+    pub fn new() -> Recording {
+        Recording {
+            version: RECORDING_VERSION,
+            frame_dt: FRAME_DT,
+            ..Default::default()
+        }
+    }
+
+    pub fn push(&mut self, mut pressed: Vec<Action>) {
+        pressed.sort();
+        match self.frames.last_mut() {
+            Some((count, last)) if *last == pressed => *count += 1,
+            _ => self.frames.push((1, pressed)),
+        }
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.iter().map(|(count, _)| *count as usize).sum()
+    }
+
+    pub fn frame(&self, index: usize) -> Option<&[Action]> {
+        let mut remaining = index;
+        for (count, pressed) in &self.frames {
+            if remaining < *count as usize {
+                return Some(pressed);
+            }
+            remaining -= *count as usize;
+        }
+        None
+    }
+
+    pub fn load(path: &PathBuf) -> Result<Recording, String> {
+        let contents = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        let recording: Recording = ron::from_str(&contents).map_err(|err| err.to_string())?;
+        if recording.version != RECORDING_VERSION {
+            return Err(format!("unsupported recording version {}", recording.version));
+        }
+        Ok(recording)
+    }
+
+    pub fn save(&self, path: &PathBuf) -> Result<(), String> {
+        let contents = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|err| err.to_string())?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+        }
+        fs::write(path, contents).map_err(|err| err.to_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum ReplayMode {
+    #[default]
+    Off,
+    Record(PathBuf),
+    Replay(PathBuf),
+}
+
+impl ReplayMode {
+    /// Reads `--record <file>` or `--replay <file>` from the command line
+    pub fn from_args() -> ReplayMode {
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match (arg.as_str(), args.next()) {
+                ("--record", Some(path)) => return ReplayMode::Record(path.into()),
+                ("--replay", Some(path)) => return ReplayMode::Replay(path.into()),
+                _ => (),
+            }
+        }
+        ReplayMode::Off
+    }
+}
+
+#[derive(Debug, Default, Resource)]
+pub struct Replay {
+    pub mode: ReplayMode,
+    pub recording: Recording,
+    /// Frames elapsed since the player first spawned
+    pub frame: usize,
+    pub started: bool,
+    pub diverged: bool,
+}
+
+pub fn record_actions(
+    mut replay: ResMut<Replay>,
+    actions: Res<ButtonInput<Action>>,
+    player_query: Query<(), With<Player>>,
+) {
+    replay.started |= !player_query.is_empty();
+    if replay.started {
+        let pressed = actions.get_pressed().copied().collect();
+        replay.recording.push(pressed);
+    }
+}
+
+pub fn replay_actions(
+    mut replay: ResMut<Replay>,
+    mut actions: ResMut<ButtonInput<Action>>,
+    player_query: Query<(), With<Player>>,
+) {
+    replay.started |= !player_query.is_empty();
+    if !replay.started {
+        return;
+    }
+
+    // Real input is overwritten completely, the recording is the only source of actions
+    actions.reset_all();
+
+    // Restore last frame's state first so just_pressed/just_released match the recorded run
+    if let Some(previous) = replay.frame.checked_sub(1).and_then(|index| replay.recording.frame(index)) {
+        for action in previous {
+            actions.press(*action);
+        }
+        actions.clear();
+    }
+
+    let current = replay.recording.frame(replay.frame).unwrap_or_default();
+    for action in Action::ALL {
+        if current.contains(&action) {
+            actions.press(action);
+        } else {
+            actions.release(action);
+        }
+    }
+}
+
+pub fn track_trajectory(
+    mut replay: ResMut<Replay>,
+    player_query: Query<&Transform, With<Player>>,
+) {
+    if !replay.started {
+        return;
+    }
+
+    if replay.frame.is_multiple_of(TRAJECTORY_INTERVAL) {
+        let position = player_query
+            .get_single()
+            .map(|transform| transform.translation.truncate())
+            .unwrap_or_default();
+        let sample = replay.frame / TRAJECTORY_INTERVAL;
+
+        if let ReplayMode::Record(_) = replay.mode {
+            replay.recording.trajectory.push(position);
+        } else if let Some(expected) = replay.recording.trajectory.get(sample).copied() {
+            if !replay.diverged && expected.distance(position) > TRAJECTORY_TOLERANCE {
+                error!(
+                    "Replay diverged at frame {}: expected player at {}, found {}",
+                    replay.frame, expected, position
+                );
+                replay.diverged = true;
+            }
+        }
+    }
+
+    replay.frame += 1;
+}
+
+pub fn finish_replay(replay: Res<Replay>, mut app_exit: EventWriter<AppExit>) {
+    if replay.started && replay.frame >= replay.recording.frame_count() {
+        if replay.diverged {
+            app_exit.send(AppExit::error());
+        } else {
+            info!("Replay finished after {} frames, trajectory matched", replay.frame);
+            app_exit.send(AppExit::Success);
+        }
+    }
+}
+
+pub fn save_recording(replay: Res<Replay>, mut app_exit: EventReader<AppExit>) {
+    if app_exit.read().next().is_none() {
+        return;
+    }
+
+    if let ReplayMode::Record(path) = &replay.mode {
+        match replay.recording.save(path) {
+            Ok(()) => info!("Saved {} recorded frames to {}", replay.frame, path.display()),
+            Err(err) => error!("Failed to save recording to {}: {}", path.display(), err),
+        }
+    }
+}
+
+/// Past the title menu and loading screen, which are left to real input since how long they take varies
+fn in_game(state: Res<State<AppState>>) -> bool {
+    !matches!(state.get(), AppState::TitleMenu | AppState::Loading)
+}
+
+/// Slows rapier's fixed step along with virtual time, like the variable timestep does outside replays
+fn scale_fixed_timestep(replay: Res<Replay>, virtual_time: Res<Time<Virtual>>, mut timestep_mode: ResMut<TimestepMode>) {
+    if let TimestepMode::Fixed { dt, .. } = &mut *timestep_mode {
+        *dt = replay.recording.frame_dt * virtual_time.relative_speed();
+    }
+}
+
+fn is_recording(replay: Res<Replay>) -> bool {
+    matches!(replay.mode, ReplayMode::Record(_))
+}
+
+fn is_replaying(replay: Res<Replay>) -> bool {
+    matches!(replay.mode, ReplayMode::Replay(_))
+}
+
+/// Records or replays the action state that drives the player, selected with
+/// `--record <file>` or `--replay <file>`. In either mode time and rapier advance
+/// by a fixed [FRAME_DT] per frame so the simulation is reproducible.
+///
+/// Must be added after `RapierPhysicsPlugin` so the fixed timestep wins.
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        let mode = ReplayMode::from_args();
+        let recording = match &mode {
+            ReplayMode::Off => return,
+            ReplayMode::Record(_) => Recording::new(),
+            ReplayMode::Replay(path) => match Recording::load(path) {
+                Ok(recording) => recording,
+                Err(err) => {
+                    error!("Failed to load recording {}: {}", path.display(), err);
+                    return;
+                }
+            },
+        };
+        add_replay(app, mode, recording);
+    }
+}
+
+/// Records into or replays `recording` from the first frame a player exists,
+/// stepping time and rapier by the recording's fixed timestep
+pub fn add_replay(app: &mut App, mode: ReplayMode, recording: Recording) {
+    app
+        .insert_resource(TimestepMode::Fixed {
+            dt: recording.frame_dt,
+            substeps: 1,
+        })
+        .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f32(
+            recording.frame_dt,
+        )))
+        .insert_resource(Replay {
+            mode,
+            recording,
+            ..Default::default()
+        })
+        .add_systems(PreUpdate, (
+            record_actions.run_if(is_recording),
+            replay_actions.run_if(is_replaying),
+        ).after(update_action_state).run_if(in_game))
+        .add_systems(PreUpdate, scale_fixed_timestep)
+        .add_systems(Last, (
+            track_trajectory.run_if(in_game),
+            finish_replay.run_if(is_replaying),
+            save_recording.run_if(is_recording),
+        ).chain());
+}
@@ -0,0 +1,447 @@
+use std::{collections::BTreeSet, fs, path::PathBuf};
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_ecs_ldtk::prelude::*;
+use bevy_rapier2d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    abilities::{Abilities, Ability},
+    actions::{Action, InputLock, Menu},
+    app_state::AppState,
+    boss::BossFight,
+    dialogue::DialogueRunner,
+    doors::{DoorTransition, TransitionPhase},
+    flags::GameFlags,
+    game_flow::LdtkProjects,
+    health::Health,
+    inventory::{Equipment, Inventory, InventoryScreen},
+    items::ItemType,
+    locale::Localizer,
+    map::{ExploredLevels, MapScreen},
+    player::Player,
+    quests::QuestLog,
+    shop::ShopState,
+    world_state::{EntityState, WorldState},
+};
+
+/// Where save slots are written, one `slot_<n>.ron` per slot
+const SAVE_DIR: &str = "saves";
+pub const SLOT_COUNT: usize = 3;
+
+/// Bump this when the save format changes in a way `#[serde(default)]` can't cover,
+/// and teach [SaveData::migrate] how to bring the older version up to date.
+pub const SAVE_VERSION: u32 = 3;
+
+/// Everything needed to put the game back the way it was
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SaveData {
+    pub version: u32,
+    /// Iid of the level the player was in
+    pub level: String,
+    /// Shown in the slot list
+    pub level_name: String,
+    pub player_position: Vec2,
+    pub health: i32,
+    pub inventory: Inventory,
+    /// Only written by versions before 3
+    #[serde(skip_serializing_if = "Abilities::is_empty")]
+    pub abilities: Abilities,
+    pub equipment: Equipment,
+    pub flags: GameFlags,
+    pub quests: QuestLog,
+    pub world: WorldState,
+    pub explored: ExploredLevels,
+    /// Iids of picked up entities, only written by version 1
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub collected: BTreeSet<String>,
+}
+
+impl SaveData {
+    pub fn slot_path(slot: usize) -> PathBuf {
+        PathBuf::from(SAVE_DIR).join(format!("slot_{}.ron", slot + 1))
+    }
+
+    pub fn parse(contents: &str) -> Result<SaveData, String> {
+        let mut data: SaveData = ron::from_str(contents).map_err(|err| err.to_string())?;
+        if data.version > SAVE_VERSION {
+            return Err(format!("save is version {}, newer than this game's {}", data.version, SAVE_VERSION));
+        }
+        data.migrate();
+        Ok(data)
+    }
+
+    /// Upgrades saves written by older builds. Fields that didn't exist yet
+    /// already come back as their defaults.
+    fn migrate(&mut self) {
+        if self.version < 2 {
+            for iid in std::mem::take(&mut self.collected) {
+                self.world.entities.insert(iid, EntityState::Collected);
+            }
+        }
+        // Blocking used to be an ability, now the shield has to be worn
+        if self.version < 3 && self.abilities.has(Ability::Block) {
+            self.abilities.remove(Ability::Block);
+            self.equipment.equip(&mut self.inventory, ItemType::SimpleShield);
+        }
+        self.version = SAVE_VERSION;
+    }
+
+    pub fn load(slot: usize) -> Result<SaveData, String> {
+        let contents = fs::read_to_string(SaveData::slot_path(slot)).map_err(|err| err.to_string())?;
+        SaveData::parse(&contents)
+    }
+
+    pub fn save(&self, slot: usize) -> Result<(), String> {
+        let path = SaveData::slot_path(slot);
+        let contents = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()).map_err(|err| err.to_string())?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+        }
+        fs::write(path, contents).map_err(|err| err.to_string())
+    }
+}
+
+/// Player state waiting for the player entity to respawn after a load
+#[derive(Debug, Resource)]
+pub struct PendingLoad(pub SaveData);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SaveMenuMode {
+    #[default]
+    Save,
+    Load,
+}
+
+#[derive(Debug, Default, Resource)]
+pub struct SaveMenuState {
+    pub open: bool,
+    pub mode: SaveMenuMode,
+    pub selected: usize,
+    /// Summary of each slot's save, `None` for empty slots
+    pub slots: Vec<Option<String>>,
+    /// String table key of the last save or load's result
+    pub message: Option<&'static str>,
+}
+
+impl SaveMenuState {
+    fn refresh_slots(&mut self) {
+        self.slots = (0..SLOT_COUNT)
+            .map(|slot| SaveData::load(slot).ok().map(|data| data.level_name))
+            .collect();
+    }
+}
+
+#[derive(Component)]
+pub struct SaveMenuUI;
+
+#[derive(Component)]
+pub struct SaveMenuText;
+
+pub fn spawn_save_menu(mut commands: Commands) {
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(60.0),
+                left: Val::Percent(50.0),
+                margin: UiRect::left(Val::Px(-140.0)),
+                width: Val::Px(280.0),
+                padding: UiRect::all(Val::Px(10.0)),
+                ..Default::default()
+            },
+            Visibility::Hidden,
+            BackgroundColor::from(Color::srgba(0.1, 0.1, 0.1, 0.9)),
+            SaveMenuUI,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new(""),
+                TextFont {
+                    font_size: 16.0,
+                    ..Default::default()
+                },
+                TextColor(Color::WHITE),
+                SaveMenuText,
+            ));
+        });
+}
+
+pub fn toggle_save_menu(
+    actions: Res<ButtonInput<Action>>,
+    mut menu_state: ResMut<SaveMenuState>,
+    mut lock: ResMut<InputLock>,
+) {
+    let mode = if actions.just_pressed(Action::SaveGame) {
+        SaveMenuMode::Save
+    } else if actions.just_pressed(Action::LoadGame) {
+        SaveMenuMode::Load
+    } else {
+        if menu_state.open && actions.just_pressed(Action::MenuBack) {
+            menu_state.open = false;
+            lock.close_menu(Menu::SaveSlots);
+        }
+        return;
+    };
+    // Another menu has the controls
+    if !lock.open_menu(Menu::SaveSlots) {
+        return;
+    }
+
+    menu_state.open = true;
+    menu_state.mode = mode;
+    menu_state.message = None;
+    menu_state.refresh_slots();
+}
+
+/// Everything a save is made from
+#[derive(SystemParam)]
+pub struct SaveSources<'w, 's> {
+    player_query: Query<'w, 's, (&'static Transform, &'static Health, &'static Inventory, &'static Equipment), With<Player>>,
+    flags: Res<'w, GameFlags>,
+    quests: Res<'w, QuestLog>,
+    world_state: Res<'w, WorldState>,
+    explored: Res<'w, ExploredLevels>,
+    level_selection: Res<'w, LevelSelection>,
+}
+
+/// Whatever is underway that loading would leave half done
+#[derive(SystemParam)]
+pub struct LoadBlockers<'w> {
+    dialogue_runner: Res<'w, DialogueRunner>,
+    shop: Res<'w, ShopState>,
+    inventory_screen: Res<'w, InventoryScreen>,
+    map_screen: Res<'w, MapScreen>,
+    door_transition: Res<'w, DoorTransition>,
+}
+
+impl LoadBlockers<'_> {
+    /// Level transitions and the boss intro hold the cutscene lock, everything else is checked here
+    fn busy(&self, lock: &InputLock) -> bool {
+        lock.cutscene
+            || self.dialogue_runner.is_active()
+            || self.shop.open.is_some()
+            || self.inventory_screen.open
+            || self.map_screen.open
+            || self.door_transition.phase != TransitionPhase::Idle
+    }
+}
+
+pub fn navigate_save_menu(
+    mut commands: Commands,
+    actions: Res<ButtonInput<Action>>,
+    mut menu_state: ResMut<SaveMenuState>,
+    mut lock: ResMut<InputLock>,
+    sources: SaveSources,
+    ldtk_projects: LdtkProjects,
+    blockers: LoadBlockers,
+) {
+    if !menu_state.open {
+        return;
+    }
+    if actions.just_pressed(Action::MenuUp) {
+        menu_state.selected = (menu_state.selected + SLOT_COUNT - 1) % SLOT_COUNT;
+    }
+    if actions.just_pressed(Action::MenuDown) {
+        menu_state.selected = (menu_state.selected + 1) % SLOT_COUNT;
+    }
+    if !actions.just_pressed(Action::MenuConfirm) {
+        return;
+    }
+
+    let slot = menu_state.selected;
+
+    match menu_state.mode {
+        SaveMenuMode::Save => {
+            let Ok((transform, health, inventory, equipment)) = sources.player_query.get_single() else { return };
+            let Some(level) = ldtk_projects.selected_level(&sources.level_selection) else {
+                warn!("Not saving, the current level isn't in any loaded LDtk project");
+                return;
+            };
+
+            let data = SaveData {
+                version: SAVE_VERSION,
+                level: level.iid.clone(),
+                level_name: level.identifier.clone(),
+                player_position: transform.translation.truncate(),
+                health: health.current(),
+                inventory: inventory.clone(),
+                abilities: Abilities::default(),
+                equipment: equipment.clone(),
+                flags: sources.flags.clone(),
+                quests: sources.quests.clone(),
+                world: sources.world_state.clone(),
+                explored: sources.explored.clone(),
+                collected: BTreeSet::new(),
+            };
+            menu_state.message = Some(match data.save(slot) {
+                Ok(()) => {
+                    info!("Saved to slot {}", slot + 1);
+                    "save.saved"
+                }
+                Err(err) => {
+                    warn!("Failed to save to {:?}: {}", SaveData::slot_path(slot), err);
+                    "save.failed"
+                }
+            });
+            menu_state.refresh_slots();
+        }
+        SaveMenuMode::Load => {
+            if blockers.busy(&lock) {
+                menu_state.message = Some("save.busy");
+                return;
+            }
+            let data = match SaveData::load(slot) {
+                Ok(data) => data,
+                Err(err) => {
+                    warn!("Failed to load {:?}: {}", SaveData::slot_path(slot), err);
+                    menu_state.message = Some("save.failed");
+                    return;
+                }
+            };
+            info!("Loading slot {} in {}", slot + 1, data.level_name);
+
+            commands.insert_resource(data.flags.clone());
+            commands.insert_resource(data.quests.clone());
+            commands.insert_resource(data.world.clone());
+            commands.insert_resource(data.explored.clone());
+            commands.insert_resource(LevelSelection::iid(data.level.clone()));
+            // A half-finished encounter starts over
+            commands.insert_resource(BossFight::default());
+            for (world, _) in ldtk_projects.iter() {
+                commands.entity(world).insert(Respawn);
+            }
+            commands.insert_resource(PendingLoad(data));
+
+            menu_state.open = false;
+            *lock = InputLock::default();
+        }
+    }
+}
+
+/// Puts the respawned player where the save left them
+#[allow(clippy::type_complexity)]
+pub fn apply_pending_load(
+    mut commands: Commands,
+    pending: Option<Res<PendingLoad>>,
+    mut player_query: Query<(&mut Transform, &mut Velocity, &mut Health, &mut Inventory, &mut Equipment), Added<Player>>,
+) {
+    let Some(pending) = pending else { return };
+    let Ok((mut transform, mut velocity, mut health, mut inventory, mut equipment)) = player_query.get_single_mut() else {
+        return;
+    };
+    let data = &pending.0;
+
+    transform.translation.x = data.player_position.x;
+    transform.translation.y = data.player_position.y;
+    *velocity = Velocity::zero();
+    health.set_current(data.health);
+    *inventory = data.inventory.clone();
+    *equipment = data.equipment.clone();
+    commands.remove_resource::<PendingLoad>();
+}
+
+pub fn update_save_menu(
+    menu_state: Res<SaveMenuState>,
+    localizer: Localizer,
+    mut menu_query: Query<&mut Visibility, With<SaveMenuUI>>,
+    mut text_query: Query<&mut Text, With<SaveMenuText>>,
+) {
+    if !menu_state.is_changed() && !localizer.changed() {
+        return;
+    }
+
+    for mut visibility in menu_query.iter_mut() {
+        visibility.set_if_neq(if menu_state.open { Visibility::Visible } else { Visibility::Hidden });
+    }
+
+    let title = match menu_state.mode {
+        SaveMenuMode::Save => "save.title_save",
+        SaveMenuMode::Load => "save.title_load",
+    };
+    let mut lines = vec![localizer.get(title), String::new()];
+    for (slot, summary) in menu_state.slots.iter().enumerate() {
+        let cursor = if slot == menu_state.selected { ">" } else { " " };
+        let args = [
+            ("slot".to_string(), (slot + 1).to_string()),
+            ("level".to_string(), summary.clone().unwrap_or_default()),
+        ];
+        let key = if summary.is_some() { "save.slot" } else { "save.empty" };
+        lines.push(format!("{} {}", cursor, localizer.format(key, &args)));
+    }
+    if let Some(message) = menu_state.message {
+        lines.push(String::new());
+        lines.push(localizer.get(message));
+    }
+
+    for mut text in text_query.iter_mut() {
+        text.0 = lines.join("\n");
+    }
+}
+
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<SaveMenuState>()
+            .add_systems(Startup, spawn_save_menu)
+            .add_systems(Update, (
+                apply_pending_load,
+                (toggle_save_menu, navigate_save_menu, update_save_menu).chain(),
+            ).run_if(in_state(AppState::Playing)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inventory::EquipSlot;
+
+    #[test]
+    fn older_saves_fill_in_new_fields() {
+        let data = SaveData::parse(
+            r#"(
+                version: 1,
+                level: "a2a50ff0-66b0-11ec-9cd7-c721746049b9",
+                player_position: (60.0, 40.0),
+                health: 80,
+                collected: ["9f901940-c210-11ef-8fc0-03a596055bbd"],
+            )"#,
+        )
+        .unwrap();
+
+        assert_eq!(data.version, SAVE_VERSION);
+        assert_eq!(data.health, 80);
+        assert_eq!(data.player_position, Vec2::new(60.0, 40.0));
+        assert_eq!(data.inventory.count(ItemType::Gold), 0);
+        // Version 1 only tracked picked up entities
+        assert!(data.collected.is_empty());
+        assert_eq!(
+            data.world.get(&EntityIid::new("9f901940-c210-11ef-8fc0-03a596055bbd")),
+            Some(EntityState::Collected)
+        );
+    }
+
+    #[test]
+    fn block_ability_becomes_a_worn_shield() {
+        let data = SaveData::parse(
+            r#"(
+                version: 2,
+                inventory: (items: {SimpleShield: 1}),
+                abilities: (unlocked: [Block]),
+            )"#,
+        )
+        .unwrap();
+
+        assert!(!data.abilities.has(Ability::Block));
+        assert_eq!(data.equipment.get(EquipSlot::OffHand), Some(ItemType::SimpleShield));
+        assert_eq!(data.inventory.count(ItemType::SimpleShield), 0);
+    }
+
+    #[test]
+    fn saves_from_newer_versions_are_rejected() {
+        assert!(SaveData::parse("(version: 999)").is_err());
+    }
+}
@@ -0,0 +1,323 @@
+use std::str::FromStr;
+
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+
+use crate::{
+    actions::{Action, ActionBindings, InputBinding, InputLock, Menu},
+    app_state::AppState,
+    colliders::spawn_shop_sensors,
+    inventory::Inventory,
+    items::{ItemCollected, ItemType},
+    locale::Localizer,
+    player::Player,
+};
+
+/// Frames per second of `shop_anim.png`
+const ANIMATION_FPS: f32 = 8.0;
+const ANIMATION_FRAMES: usize = 6;
+
+/// What the shop pays for something it also sells, as a fraction of the price
+const SELL_RATIO: f32 = 0.5;
+
+/// Items for sale and their price in Gold, from the LDtk `stock` and `prices` fields
+#[derive(Debug, Clone, Default, Component)]
+pub struct Shop {
+    pub stock: Vec<(ItemType, u32)>,
+}
+
+impl Shop {
+    pub fn from_field(entity_instance: &EntityInstance) -> Shop {
+        // Unknown items stay in until the zip so prices still line up, then get dropped
+        let items: Vec<Option<ItemType>> = entity_instance
+            .iter_enums_field("stock")
+            .into_iter()
+            .flatten()
+            .map(|field| ItemType::from_str(field).ok())
+            .collect();
+        let prices: Vec<u32> = entity_instance
+            .iter_ints_field("prices")
+            .into_iter()
+            .flatten()
+            .map(|price| (*price).max(0) as u32)
+            .collect();
+
+        if items.len() != prices.len() {
+            warn!("Shop has {} stock items but {} prices", items.len(), prices.len());
+        }
+
+        Shop {
+            stock: items
+                .into_iter()
+                .zip(prices)
+                .filter_map(|(item_type, price)| Some((item_type?, price)))
+                .collect(),
+        }
+    }
+
+    pub fn sell_price(price: u32) -> u32 {
+        ((price as f32 * SELL_RATIO) as u32).max(1)
+    }
+
+    /// Everything on offer: the whole stock, then whatever the player holds that the shop deals in
+    pub fn rows(&self, inventory: &Inventory) -> Vec<ShopRow> {
+        let buy = self.stock.iter().map(|&(item_type, price)| ShopRow::Buy(item_type, price));
+        let sell = self
+            .stock
+            .iter()
+            .filter(|(item_type, _)| *item_type != ItemType::Gold && inventory.count(*item_type) > 0)
+            .map(|&(item_type, price)| ShopRow::Sell(item_type, Shop::sell_price(price)));
+        buy.chain(sell).collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShopRow {
+    Buy(ItemType, u32),
+    Sell(ItemType, u32),
+}
+
+#[derive(Default, Bundle, LdtkEntity)]
+pub struct ShopBundle {
+    #[with(Shop::from_field)]
+    shop: Shop,
+    #[sprite_sheet("shop_anim.png", 118, 128, 6, 1, 0, 0, 0)]
+    sprite_sheet: Sprite,
+}
+
+/// Sent from the interaction prompt
+#[derive(Event, Debug, Clone)]
+pub struct OpenShop {
+    pub shop: Entity,
+}
+
+#[derive(Debug, Default, Resource)]
+pub struct ShopState {
+    pub open: Option<Entity>,
+    pub selected: usize,
+    /// String table key of the last transaction's result
+    pub message: Option<&'static str>,
+}
+
+#[derive(Component)]
+pub struct ShopUI;
+
+#[derive(Component)]
+pub struct ShopText;
+
+pub fn animate_shops(time: Res<Time>, mut query: Query<&mut Sprite, With<Shop>>) {
+    let frame = (time.elapsed_secs() * ANIMATION_FPS) as usize % ANIMATION_FRAMES;
+    for mut sprite in query.iter_mut() {
+        if let Some(atlas) = sprite.texture_atlas.as_mut() {
+            atlas.index = frame;
+        }
+    }
+}
+
+pub fn spawn_shop_ui(mut commands: Commands) {
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(60.0),
+                left: Val::Percent(50.0),
+                margin: UiRect::left(Val::Px(-160.0)),
+                width: Val::Px(320.0),
+                padding: UiRect::all(Val::Px(10.0)),
+                ..Default::default()
+            },
+            Visibility::Hidden,
+            BackgroundColor::from(Color::srgba(0.2, 0.2, 0.2, 0.9)),
+            ShopUI,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new(""),
+                TextFont {
+                    font_size: 16.0,
+                    ..Default::default()
+                },
+                TextColor(Color::WHITE),
+                ShopText,
+            ));
+        });
+}
+
+pub fn open_shop(
+    mut events: EventReader<OpenShop>,
+    mut state: ResMut<ShopState>,
+    mut lock: ResMut<InputLock>,
+    mut ui_query: Query<&mut Visibility, With<ShopUI>>,
+) {
+    for event in events.read() {
+        // Another menu has the controls
+        if !lock.open_menu(Menu::Shop) {
+            continue;
+        }
+        *state = ShopState {
+            open: Some(event.shop),
+            ..Default::default()
+        };
+        for mut visibility in ui_query.iter_mut() {
+            *visibility = Visibility::Visible;
+        }
+    }
+}
+
+pub fn handle_shop_input(
+    actions: Res<ButtonInput<Action>>,
+    mut state: ResMut<ShopState>,
+    mut lock: ResMut<InputLock>,
+    shop_query: Query<&Shop>,
+    mut player_query: Query<&mut Inventory, With<Player>>,
+    mut ui_query: Query<&mut Visibility, With<ShopUI>>,
+    mut collected: EventWriter<ItemCollected>,
+) {
+    let Some(shop_entity) = state.open else { return };
+    let (Ok(shop), Ok(mut inventory)) = (shop_query.get(shop_entity), player_query.get_single_mut()) else {
+        return;
+    };
+
+    if actions.just_pressed(Action::MenuBack) {
+        state.open = None;
+        lock.close_menu(Menu::Shop);
+        for mut visibility in ui_query.iter_mut() {
+            *visibility = Visibility::Hidden;
+        }
+        return;
+    }
+
+    let rows = shop.rows(&inventory);
+    if rows.is_empty() {
+        return;
+    }
+    if actions.just_pressed(Action::MenuUp) {
+        state.selected = (state.selected + rows.len() - 1) % rows.len();
+    }
+    if actions.just_pressed(Action::MenuDown) {
+        state.selected = (state.selected + 1) % rows.len();
+    }
+
+    if actions.just_pressed(Action::MenuConfirm) {
+        let Some(row) = rows.get(state.selected) else { return };
+        state.message = Some(match *row {
+            ShopRow::Buy(item_type, price) => {
+                if inventory.remove(ItemType::Gold, price) {
+                    inventory.add(item_type, 1);
+                    collected.send(ItemCollected { item_type, count: 1 });
+                    "shop.bought"
+                } else {
+                    "shop.no_gold"
+                }
+            }
+            ShopRow::Sell(item_type, price) => {
+                if inventory.remove(item_type, 1) {
+                    inventory.add(ItemType::Gold, price);
+                    collected.send(ItemCollected {
+                        item_type: ItemType::Gold,
+                        count: price,
+                    });
+                }
+                "shop.sold"
+            }
+        });
+        // Selling the last of something removes its row
+        let len = shop.rows(&inventory).len();
+        state.selected = state.selected.min(len.saturating_sub(1));
+    }
+}
+
+pub fn update_shop_ui(
+    state: Res<ShopState>,
+    shop_query: Query<&Shop>,
+    player_query: Query<Ref<Inventory>, With<Player>>,
+    bindings: Res<ActionBindings>,
+    localizer: Localizer,
+    mut text_query: Query<&mut Text, With<ShopText>>,
+) {
+    let Some(shop) = state.open.and_then(|entity| shop_query.get(entity).ok()) else { return };
+    let Ok(inventory) = player_query.get_single() else { return };
+    if !state.is_changed() && !inventory.is_changed() && !localizer.changed() {
+        return;
+    }
+
+    let key_label = |action| {
+        bindings
+            .get(action)
+            .first()
+            .map(InputBinding::label)
+            .unwrap_or_default()
+    };
+    let arg = |name: &str, value: String| (name.to_string(), value);
+
+    let mut lines = vec![
+        localizer.get("shop.title"),
+        localizer.format("shop.gold", &[arg("count", inventory.count(ItemType::Gold).to_string())]),
+        String::new(),
+    ];
+    for (index, row) in shop.rows(&inventory).iter().enumerate() {
+        let (key, item_type, price) = match *row {
+            ShopRow::Buy(item_type, price) => ("shop.buy", item_type, price),
+            ShopRow::Sell(item_type, price) => ("shop.sell", item_type, price),
+        };
+        let cursor = if index == state.selected { ">" } else { " " };
+        let text = localizer.format(key, &[arg("item", item_type.name_key()), arg("price", price.to_string())]);
+        lines.push(format!("{} {}", cursor, text));
+    }
+    lines.push(String::new());
+    if let Some(message) = state.message {
+        lines.push(localizer.get(message));
+    }
+    lines.push(localizer.format(
+        "shop.hint",
+        &[arg("confirm", key_label(Action::MenuConfirm)), arg("back", key_label(Action::MenuBack))],
+    ));
+
+    for mut text in text_query.iter_mut() {
+        text.0 = lines.join("\n");
+    }
+}
+
+pub struct ShopPlugin;
+
+impl Plugin for ShopPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_event::<OpenShop>()
+            .init_resource::<ShopState>()
+            .add_systems(Startup, spawn_shop_ui)
+            .add_systems(Update, (
+                spawn_shop_sensors,
+                animate_shops,
+                // Input first so the key that opened the shop doesn't also buy something
+                (handle_shop_input, open_shop, update_shop_ui).chain(),
+            ).run_if(in_state(AppState::Playing)))
+            .register_ldtk_entity::<ShopBundle>("Shop");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_stocked_items_can_be_sold() {
+        let shop = Shop {
+            stock: vec![(ItemType::SmallHealingPotion, 3), (ItemType::HealScroll, 8)],
+        };
+        let mut inventory = Inventory::default();
+        inventory.add(ItemType::Gold, 5);
+        inventory.add(ItemType::HealScroll, 1);
+        inventory.add(ItemType::SimpleShield, 1);
+
+        assert_eq!(
+            shop.rows(&inventory),
+            vec![
+                ShopRow::Buy(ItemType::SmallHealingPotion, 3),
+                ShopRow::Buy(ItemType::HealScroll, 8),
+                ShopRow::Sell(ItemType::HealScroll, 4),
+            ]
+        );
+        assert_eq!(Shop::sell_price(1), 1);
+    }
+}
@@ -0,0 +1,66 @@
+use bevy::prelude::*;
+
+use crate::{
+    actions::Action,
+    inventory::Equipment,
+    player::Player,
+    state_machine::PlayerEvent,
+};
+
+const MAX_STAMINA: f32 = 100.;
+/// Spent per second while holding up the shield
+const BLOCK_DRAIN: f32 = 30.;
+const REGEN: f32 = 20.;
+/// Once emptied, blocking only works again after refilling this much
+const RECOVER_FRACTION: f32 = 0.3;
+
+#[derive(Debug, Component, Reflect)]
+pub struct Stamina {
+    pub current: f32,
+    pub max: f32,
+    /// Ran out, and hasn't recovered enough to block yet
+    pub exhausted: bool,
+}
+
+impl Default for Stamina {
+    fn default() -> Self {
+        Stamina {
+            current: MAX_STAMINA,
+            max: MAX_STAMINA,
+            exhausted: false,
+        }
+    }
+}
+
+impl Stamina {
+    pub fn can_block(&self) -> bool {
+        !self.exhausted && self.current > 0.
+    }
+
+    pub fn fraction(&self) -> f32 {
+        self.current / self.max
+    }
+}
+
+pub fn update_stamina(
+    time: Res<Time>,
+    actions: Res<ButtonInput<Action>>,
+    mut query: Query<(&mut Stamina, &Equipment), With<Player>>,
+    mut player_events: EventWriter<PlayerEvent>,
+) {
+    let Ok((mut stamina, equipment)) = query.get_single_mut() else { return };
+    let delta = time.delta_secs();
+
+    if equipment.can_block() && actions.pressed(Action::Block) && stamina.can_block() {
+        stamina.current = (stamina.current - BLOCK_DRAIN * delta).max(0.);
+        if stamina.current == 0. {
+            stamina.exhausted = true;
+            player_events.send(PlayerEvent::BlockEnded);
+        }
+    } else if stamina.current < stamina.max {
+        stamina.current = (stamina.current + REGEN * delta).min(stamina.max);
+        if stamina.exhausted && stamina.fraction() >= RECOVER_FRACTION {
+            stamina.exhausted = false;
+        }
+    }
+}
@@ -0,0 +1,444 @@
+//! Headless harness for driving the gameplay plugins from tests.
+//!
+//! Builds an [App] with `MinimalPlugins`, `LdtkPlugin` and rapier but no window or
+//! renderer, loads either `platformer-world.ldtk` or an inline ASCII level, and steps
+//! frames with scripted [Action]s at the same fixed timestep replays use.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use bevy::{
+    image::{CompressedImageFormats, ImageLoader},
+    render::render_resource::Shader,
+    state::app::StatesPlugin,
+    time::TimeUpdateStrategy,
+};
+use bevy::prelude::*;
+use bevy_ecs_ldtk::{ldtk::LdtkJson, prelude::*};
+use bevy_rapier2d::prelude::*;
+use serde_json::{json, Value};
+
+use crate::{
+    actions::Action,
+    app_state::AppState,
+    climbing::ClimbingPlugin,
+    game_flow::{no_level, select_start_level, update_level_selection, StartWorld, GRAVITY},
+    ground_detection::{GroundDetection, GroundDetectionPlugin},
+    level_transition::LevelEntered,
+    player::{Player, PlayerPlugin},
+    replay::{add_replay, Recording, Replay, ReplayMode, FRAME_DT},
+    state_machine::PlayerState,
+    walls::WallPlugin,
+};
+
+const WORLD_FILE: &str = "platformer-world.ldtk";
+const INLINE_FILE: &str = "inline.ldtk";
+/// Short run of walking and jumping, replayed by the tests to catch nondeterminism
+const REPLAY_FILE: &str = "replays/walk_and_jump.replay.ron";
+const GRID_SIZE: usize = 24;
+
+/// Upper bound on frames spent waiting for assets, so a broken project fails instead of hanging
+const MAX_LOAD_FRAMES: usize = 10_000;
+
+pub enum TestProject {
+    /// The real `assets/platformer-world.ldtk`
+    World,
+    /// A single level drawn as rows of text, top row first:
+    /// `#` is a wall tile, `P` is where the player spawns, anything else is empty
+    Inline(&'static [&'static str]),
+}
+
+pub struct TestApp {
+    pub app: App,
+}
+
+fn assets_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("assets")
+}
+
+/// The real world file, parsed straight from disk without an [App]
+pub fn world_json() -> LdtkJson {
+    let contents = fs::read_to_string(assets_dir().join(WORLD_FILE)).expect("world file should exist");
+    serde_json::from_str(&contents).expect("world file should parse")
+}
+
+/// Builds a one-level project from the real one, so it keeps its layer, entity and
+/// tileset definitions but gets the level layout from `rows`
+fn inline_project(rows: &[&str]) -> String {
+    let template = fs::read_to_string(assets_dir().join(WORLD_FILE)).expect("world file should exist");
+    let mut project: Value = serde_json::from_str(&template).expect("world file should be valid json");
+
+    let width = rows[0].len();
+    let height = rows.len();
+
+    let mut level = project["levels"][0].take();
+    level["pxWid"] = json!(width * GRID_SIZE);
+    level["pxHei"] = json!(height * GRID_SIZE);
+    level["worldX"] = json!(0);
+    level["worldY"] = json!(0);
+    level["bgRelPath"] = Value::Null;
+    level["__bgPos"] = Value::Null;
+    level["__neighbours"] = json!([]);
+
+    let csv: Vec<u8> = rows
+        .iter()
+        .flat_map(|row| row.chars().map(|c| if c == '#' { 1 } else { 0 }))
+        .collect();
+
+    let (player_x, player_y) = rows
+        .iter()
+        .enumerate()
+        .find_map(|(y, row)| row.find('P').map(|x| (x, y)))
+        .expect("inline level should contain a P");
+
+    for layer in level["layerInstances"].as_array_mut().expect("level should have layers") {
+        layer["__cWid"] = json!(width);
+        layer["__cHei"] = json!(height);
+
+        let layer_type = layer["__type"].as_str().unwrap_or_default().to_string();
+        match layer_type.as_str() {
+            "IntGrid" => {
+                layer["intGridCsv"] = json!(csv);
+                layer["autoLayerTiles"] = json!([]);
+                layer["gridTiles"] = json!([]);
+            }
+            "Entities" => {
+                let mut player = layer["entityInstances"]
+                    .as_array()
+                    .and_then(|entities| entities.iter().find(|e| e["__identifier"] == "Player"))
+                    .cloned()
+                    .expect("template should contain a Player");
+
+                // entity pivot is bottom-center
+                let px = [player_x * GRID_SIZE + GRID_SIZE / 2, (player_y + 1) * GRID_SIZE];
+                player["__grid"] = json!([player_x, player_y]);
+                player["px"] = json!(px);
+                player["__worldX"] = json!(px[0]);
+                player["__worldY"] = json!(px[1]);
+
+                layer["entityInstances"] = json!([player]);
+            }
+            _ => (),
+        }
+    }
+
+    project["levels"] = json!([level]);
+    project.to_string()
+}
+
+/// Copies every image from `assets/` so tilesets and sprite sheets resolve next to the inline project
+fn copy_images(from: &Path, to: &Path) {
+    for entry in fs::read_dir(from).expect("assets dir should be readable").flatten() {
+        let path = entry.path();
+        let target = to.join(entry.file_name());
+        if path.is_dir() {
+            fs::create_dir_all(&target).unwrap();
+            copy_images(&path, &target);
+        } else if path.extension().is_some_and(|ext| ext == "png") {
+            fs::copy(&path, &target).unwrap();
+        }
+    }
+}
+
+/// Clears just_pressed/just_released at the end of each frame, standing in for
+/// `update_action_state` which isn't added without a keyboard
+fn clear_scripted_actions(mut actions: ResMut<ButtonInput<Action>>) {
+    actions.clear();
+}
+
+impl TestApp {
+    pub fn new(project: TestProject) -> TestApp {
+        let (asset_dir, file) = match project {
+            TestProject::World => (assets_dir(), WORLD_FILE),
+            TestProject::Inline(rows) => {
+                // One directory per test, tests run in parallel
+                let dir = std::env::temp_dir()
+                    .join("platformer-ldtk-tests")
+                    .join(format!("{:?}", std::thread::current().id()));
+                fs::create_dir_all(&dir).unwrap();
+                copy_images(&assets_dir(), &dir);
+                fs::write(dir.join(INLINE_FILE), inline_project(rows)).unwrap();
+                (dir, INLINE_FILE)
+            }
+        };
+
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            AssetPlugin {
+                file_path: asset_dir.to_string_lossy().into_owned(),
+                ..Default::default()
+            },
+            TransformPlugin,
+            HierarchyPlugin,
+            StatesPlugin,
+        ))
+        // Normally provided by the render and sprite plugins
+        .init_asset::<Image>()
+        .init_asset::<TextureAtlasLayout>()
+        .init_asset::<Shader>()
+        .init_asset::<Mesh>()
+        .register_asset_loader(ImageLoader::new(CompressedImageFormats::NONE))
+        .add_plugins((
+            LdtkPlugin,
+            RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0),
+        ))
+        .insert_resource(TimestepMode::Fixed {
+            dt: FRAME_DT,
+            substeps: 1,
+        })
+        .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f32(FRAME_DT)))
+        .insert_resource(no_level())
+        .insert_resource(LdtkSettings {
+            level_spawn_behavior: LevelSpawnBehavior::UseWorldTranslation {
+                load_level_neighbors: true,
+            },
+            ..Default::default()
+        })
+        .init_resource::<ButtonInput<Action>>()
+        // Gameplay systems only run while playing, there's no title menu here
+        .insert_state(AppState::Playing)
+        // Sent by `update_level_selection`, nothing transitions in tests
+        .add_event::<LevelEntered>()
+        .add_plugins((WallPlugin, GroundDetectionPlugin, ClimbingPlugin, PlayerPlugin))
+        .add_systems(Startup, move |mut commands: Commands, asset_server: Res<AssetServer>, mut rapier_config: Query<&mut RapierConfiguration>| {
+            rapier_config.single_mut().gravity = GRAVITY;
+            let handle: Handle<LdtkProject> = asset_server.load(file);
+            commands.insert_resource(StartWorld(handle.clone()));
+            commands.spawn(LdtkWorldBundle {
+                ldtk_handle: handle.into(),
+                ..Default::default()
+            });
+        })
+        .add_systems(Update, (select_start_level.run_if(resource_exists::<StartWorld>), update_level_selection))
+        .add_systems(Last, clear_scripted_actions);
+
+        let mut test_app = TestApp { app };
+        test_app.run_until(|app| app.player().is_some(), MAX_LOAD_FRAMES);
+        test_app
+    }
+
+    /// Steps frames until `condition` holds, panicking after `max_frames`
+    pub fn run_until(&mut self, mut condition: impl FnMut(&mut TestApp) -> bool, max_frames: usize) {
+        for _ in 0..max_frames {
+            if condition(self) {
+                return;
+            }
+            self.app.update();
+            // asset loading happens on other threads
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        panic!("condition not met after {} frames", max_frames);
+    }
+
+    pub fn step(&mut self, frames: usize) {
+        for _ in 0..frames {
+            self.app.update();
+        }
+    }
+
+    pub fn press(&mut self, action: Action) {
+        self.app.world_mut().resource_mut::<ButtonInput<Action>>().press(action);
+    }
+
+    pub fn release(&mut self, action: Action) {
+        self.app.world_mut().resource_mut::<ButtonInput<Action>>().release(action);
+    }
+
+    /// Presses `action` for a single frame
+    pub fn tap(&mut self, action: Action) {
+        self.press(action);
+        self.step(1);
+        self.release(action);
+    }
+
+    pub fn player(&mut self) -> Option<Entity> {
+        self.app
+            .world_mut()
+            .query_filtered::<Entity, With<Player>>()
+            .get_single(self.app.world())
+            .ok()
+    }
+
+    pub fn player_position(&mut self) -> Vec2 {
+        self.app
+            .world_mut()
+            .query_filtered::<&Transform, With<Player>>()
+            .single(self.app.world())
+            .translation
+            .truncate()
+    }
+
+    pub fn player_velocity(&mut self) -> Vec2 {
+        self.app
+            .world_mut()
+            .query_filtered::<&Velocity, With<Player>>()
+            .single(self.app.world())
+            .linvel
+    }
+
+    pub fn on_ground(&mut self) -> bool {
+        self.app
+            .world_mut()
+            .query_filtered::<&GroundDetection, With<Player>>()
+            .single(self.app.world())
+            .on_ground
+    }
+
+    pub fn player_state(&self) -> PlayerState {
+        self.app.world().resource::<State<PlayerState>>().get().clone()
+    }
+
+    /// Half extents of every merged wall collider, sorted for stable comparisons
+    pub fn wall_colliders(&mut self) -> Vec<Vec2> {
+        let mut half_extents: Vec<Vec2> = self
+            .app
+            .world_mut()
+            .query::<(&Collider, &RigidBody)>()
+            .iter(self.app.world())
+            .filter(|(_, rigid_body)| **rigid_body == RigidBody::Fixed)
+            .filter_map(|(collider, _)| collider.as_cuboid().map(|cuboid| cuboid.half_extents()))
+            .collect();
+        half_extents.sort_by(|a, b| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y)));
+        half_extents
+    }
+
+    /// Records or replays from the next frame on, the way `--record` and `--replay` do
+    pub fn start_replay(&mut self, mode: ReplayMode, recording: Recording) {
+        add_replay(&mut self.app, mode, recording);
+    }
+
+    pub fn replay(&self) -> &Replay {
+        self.app.world().resource::<Replay>()
+    }
+
+    /// Holds exactly the actions `recording` has for each frame, one frame at a time
+    pub fn play_inputs(&mut self, recording: &Recording) {
+        for index in 0..recording.frame_count() {
+            let pressed = recording.frame(index).unwrap_or_default();
+            for action in Action::ALL {
+                if pressed.contains(&action) {
+                    self.press(action);
+                } else {
+                    self.release(action);
+                }
+            }
+            self.step(1);
+        }
+    }
+
+    /// Lets the player fall until it has been standing still on the ground for a moment
+    pub fn settle(&mut self) {
+        self.run_until(|app| app.on_ground() && app.player_velocity().y.abs() < 1.0, 600);
+        self.step(10);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FLAT: &[&str] = &[
+        "..........",
+        "..........",
+        "..........",
+        "..........",
+        "....P.....",
+        "..........",
+        "##########",
+    ];
+
+    #[test]
+    fn player_lands_on_floor() {
+        let mut app = TestApp::new(TestProject::Inline(FLAT));
+        let spawn = app.player_position();
+
+        app.settle();
+
+        assert!(app.on_ground());
+        assert_eq!(app.player_state(), PlayerState::Idle);
+        assert!(app.player_position().y < spawn.y);
+        // the floor's top edge is one tile above the level bottom
+        assert!(app.player_position().y > GRID_SIZE as f32);
+    }
+
+    #[test]
+    fn player_jumps_and_lands_again() {
+        let mut app = TestApp::new(TestProject::Inline(FLAT));
+        app.settle();
+        let ground = app.player_position();
+
+        app.tap(Action::Jump);
+        assert!(app.player_velocity().y > 0.0);
+        app.step(10);
+        assert!(!app.on_ground());
+
+        let mut peak = ground.y;
+        app.run_until(|app| {
+            peak = peak.max(app.player_position().y);
+            app.player_velocity().y < 0.0
+        }, 120);
+        assert!(peak > ground.y + GRID_SIZE as f32);
+
+        app.settle();
+        assert!(app.on_ground());
+        assert!((app.player_position().y - ground.y).abs() < 1.0);
+    }
+
+    #[test]
+    fn walls_merge_into_rectangles() {
+        let mut app = TestApp::new(TestProject::Inline(&[
+            "..........",
+            "#.........",
+            "#...P.....",
+            "##########",
+        ]));
+        app.step(2);
+
+        let half_tile = GRID_SIZE as f32 / 2.;
+        assert_eq!(app.wall_colliders(), vec![
+            // left column above the floor
+            Vec2::new(half_tile, half_tile * 2.),
+            // the whole floor
+            Vec2::new(half_tile * 10., half_tile),
+        ]);
+    }
+
+    #[test]
+    fn recorded_run_replays_without_diverging() {
+        const STEP_UP: &[&str] = &[
+            "#..................#",
+            "#..................#",
+            "#..................#",
+            "#...P..............#",
+            "#.........###......#",
+            "####################",
+        ];
+        let inputs = Recording::load(&assets_dir().join(REPLAY_FILE)).expect("test recording should load");
+
+        let mut recorded = TestApp::new(TestProject::Inline(STEP_UP));
+        recorded.start_replay(ReplayMode::Record(PathBuf::new()), Recording::new());
+        recorded.play_inputs(&inputs);
+        let recording = recorded.replay().recording.clone();
+        assert_eq!(recording.frames, inputs.frames);
+        assert!(!recording.trajectory.is_empty());
+
+        let mut replayed = TestApp::new(TestProject::Inline(STEP_UP));
+        replayed.start_replay(ReplayMode::Replay(PathBuf::new()), recording.clone());
+        replayed.step(recording.frame_count());
+        assert_eq!(replayed.replay().frame, recording.frame_count());
+        assert!(!replayed.replay().diverged);
+    }
+
+    #[test]
+    fn world_player_settles_on_ground() {
+        let mut app = TestApp::new(TestProject::World);
+        app.settle();
+
+        assert!(app.on_ground());
+        assert!(!app.wall_colliders().is_empty());
+    }
+}
@@ -0,0 +1,78 @@
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// What happened to an LDtk entity that should outlive its level being unloaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EntityState {
+    /// Picked up by the player
+    Collected,
+    /// Killed, e.g. a boss
+    Defeated,
+}
+
+/// Registry of LDtk entities that are gone for good, keyed by entity iid.
+/// Levels are despawned and respawned as the player moves around, so anything
+/// recorded here is removed again every time its level spawns.
+#[derive(Debug, Clone, Default, Resource, Serialize, Deserialize)]
+pub struct WorldState {
+    pub entities: BTreeMap<String, EntityState>,
+}
+
+impl WorldState {
+    pub fn record(&mut self, iid: &EntityIid, state: EntityState) {
+        self.entities.insert(iid.as_str().to_string(), state);
+    }
+
+    pub fn get(&self, iid: &EntityIid) -> Option<EntityState> {
+        self.entities.get(iid.as_str()).copied()
+    }
+}
+
+pub fn restore_entity_state(
+    mut commands: Commands,
+    world_state: Res<WorldState>,
+    query: Query<(Entity, &EntityIid), Added<EntityIid>>,
+) {
+    for (entity, iid) in query.iter() {
+        let Some(state) = world_state.get(iid) else { continue };
+        debug!("Not respawning {} ({:?})", iid, state);
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+pub struct WorldStatePlugin;
+
+impl Plugin for WorldStatePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<WorldState>()
+            .add_systems(Update, restore_entity_state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recorded_entities_stay_gone_on_respawn() {
+        let mut app = App::new();
+        app.add_plugins(WorldStatePlugin);
+
+        let mut world_state = app.world_mut().resource_mut::<WorldState>();
+        world_state.record(&EntityIid::new("shield"), EntityState::Collected);
+        world_state.record(&EntityIid::new("golem"), EntityState::Defeated);
+
+        let shield = app.world_mut().spawn(EntityIid::new("shield")).id();
+        let golem = app.world_mut().spawn(EntityIid::new("golem")).id();
+        let npc = app.world_mut().spawn(EntityIid::new("npc")).id();
+        app.update();
+
+        assert!(app.world().get_entity(shield).is_err());
+        assert!(app.world().get_entity(golem).is_err());
+        assert!(app.world().get_entity(npc).is_ok());
+    }
+}